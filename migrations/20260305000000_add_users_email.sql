@@ -0,0 +1,9 @@
+CREATE EXTENSION IF NOT EXISTS citext;
+
+ALTER TABLE users ADD COLUMN IF NOT EXISTS email CITEXT;
+
+-- Rows created before emails existed get a unique placeholder so the column can be enforced.
+UPDATE users SET email = id::text || '@backfill.invalid' WHERE email IS NULL;
+
+ALTER TABLE users ALTER COLUMN email SET NOT NULL;
+ALTER TABLE users ADD CONSTRAINT users_email_key UNIQUE (email);
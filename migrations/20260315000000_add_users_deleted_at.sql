@@ -0,0 +1,2 @@
+-- Soft delete: rows are kept for auditing and hidden from reads while deleted_at is set.
+ALTER TABLE users ADD COLUMN IF NOT EXISTS deleted_at TIMESTAMPTZ;
@@ -0,0 +1,9 @@
+-- Idempotency-Key values seen on POST /user, mapped to the user each one created.
+-- The foreign key is deferred so the key can be claimed before the user row exists.
+CREATE TABLE IF NOT EXISTS idempotency_keys (
+    key        TEXT PRIMARY KEY,
+    user_id    UUID NOT NULL REFERENCES users (id) DEFERRABLE INITIALLY DEFERRED,
+    created_at TIMESTAMPTZ NOT NULL DEFAULT NOW()
+);
+
+CREATE INDEX IF NOT EXISTS idempotency_keys_created_at_idx ON idempotency_keys (created_at);
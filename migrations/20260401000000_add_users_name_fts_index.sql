@@ -0,0 +1,3 @@
+-- Matches the expression used by GET /users/search so the planner can use the index.
+CREATE INDEX IF NOT EXISTS users_name_fts_idx
+    ON users USING GIN (to_tsvector('english', first_name || ' ' || last_name));
@@ -0,0 +1,13 @@
+-- Postal addresses, a sub-resource of users served under /user/{id}/addresses.
+CREATE TABLE IF NOT EXISTS addresses (
+    id          UUID        PRIMARY KEY,
+    user_id     UUID        NOT NULL REFERENCES users (id) ON DELETE CASCADE,
+    line1       TEXT        NOT NULL,
+    line2       TEXT,
+    city        TEXT        NOT NULL,
+    postal_code TEXT        NOT NULL,
+    country     TEXT        NOT NULL,
+    created_at  TIMESTAMPTZ NOT NULL DEFAULT NOW()
+);
+
+CREATE INDEX IF NOT EXISTS addresses_user_id_idx ON addresses (user_id);
@@ -0,0 +1,46 @@
+-- Audit trail of user mutations, written by a trigger in the same transaction as the change.
+-- Handlers tag their transaction with set_config('app.trace_id', ...) so that each row can
+-- be joined back to the trace of the request that made it.
+CREATE TABLE IF NOT EXISTS user_audit (
+    id         BIGINT      GENERATED ALWAYS AS IDENTITY PRIMARY KEY,
+    user_id    UUID        NOT NULL,
+    operation  TEXT        NOT NULL,
+    old_values JSONB,
+    new_values JSONB       NOT NULL,
+    trace_id   TEXT,
+    created_at TIMESTAMPTZ NOT NULL DEFAULT NOW()
+);
+
+CREATE INDEX IF NOT EXISTS user_audit_user_id_idx ON user_audit (user_id, id DESC);
+
+-- Soft delete and restore are updates of deleted_at, so they are told apart here.
+CREATE OR REPLACE FUNCTION audit_user_change() RETURNS TRIGGER AS $$
+DECLARE
+    operation TEXT;
+BEGIN
+    IF TG_OP = 'INSERT' THEN
+        operation := 'create';
+    ELSIF OLD.deleted_at IS NULL AND NEW.deleted_at IS NOT NULL THEN
+        operation := 'delete';
+    ELSIF OLD.deleted_at IS NOT NULL AND NEW.deleted_at IS NULL THEN
+        operation := 'restore';
+    ELSE
+        operation := 'update';
+    END IF;
+
+    INSERT INTO user_audit (user_id, operation, old_values, new_values, trace_id)
+    VALUES (
+        NEW.id,
+        operation,
+        CASE WHEN TG_OP = 'UPDATE' THEN to_jsonb(OLD) END,
+        to_jsonb(NEW),
+        NULLIF(current_setting('app.trace_id', true), '')
+    );
+    RETURN NULL;
+END;
+$$ LANGUAGE plpgsql;
+
+CREATE TRIGGER users_audit
+    AFTER INSERT OR UPDATE ON users
+    FOR EACH ROW
+    EXECUTE FUNCTION audit_user_change();
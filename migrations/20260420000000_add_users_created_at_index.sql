@@ -0,0 +1,2 @@
+-- Serves GET /users/recent, which scans newest-first from a created_at bound.
+CREATE INDEX IF NOT EXISTS users_created_at_idx ON users (created_at DESC);
@@ -0,0 +1,8 @@
+-- Profile pictures, one per user, served under /user/{id}/avatar.
+CREATE TABLE IF NOT EXISTS user_avatars (
+    user_id      UUID        PRIMARY KEY REFERENCES users (id) ON DELETE CASCADE,
+    content_type TEXT        NOT NULL,
+    data         BYTEA       NOT NULL,
+    digest       TEXT        NOT NULL,
+    updated_at   TIMESTAMPTZ NOT NULL DEFAULT NOW()
+);
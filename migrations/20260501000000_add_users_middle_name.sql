@@ -0,0 +1,2 @@
+-- Optional, so existing rows and clients that never send it keep working with NULL.
+ALTER TABLE users ADD COLUMN IF NOT EXISTS middle_name TEXT;
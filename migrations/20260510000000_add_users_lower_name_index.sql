@@ -0,0 +1,2 @@
+-- Serves GET /users/exists, which matches names case-insensitively.
+CREATE INDEX IF NOT EXISTS users_lower_name_idx ON users (lower(last_name), lower(first_name));
@@ -0,0 +1,4 @@
+-- New users get UUIDv7 ids, which start with their creation time. Inserts then append
+-- to the right edge of users_pkey instead of splitting pages all over it. Users created
+-- before the switch keep their random v4 ids; both are plain UUIDs to Postgres.
+COMMENT ON COLUMN users.id IS 'UUIDv7 for new users; users created before the switch have v4 ids';
@@ -0,0 +1,2 @@
+-- Timestamps are now read as TIMESTAMPTZ and formatted by the application.
+DROP FUNCTION IF EXISTS rfc3339(TIMESTAMPTZ);
@@ -0,0 +1,4 @@
+-- The user as POST /user returned it, replayed for a repeated Idempotency-Key even after
+-- the user has been changed or deleted. Keys claimed before this column existed have no
+-- body and replay the current row; they expire within 24 hours.
+ALTER TABLE idempotency_keys ADD COLUMN IF NOT EXISTS response_body JSONB;
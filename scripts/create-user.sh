@@ -1,9 +1,10 @@
 #!/usr/bin/env sh
 set -e
 
-FIRST_NAME="${1:?Usage: create-user.sh <first_name> <last_name>}"
-LAST_NAME="${2:?Usage: create-user.sh <first_name> <last_name>}"
+FIRST_NAME="${1:?Usage: create-user.sh <first_name> <last_name> <email>}"
+LAST_NAME="${2:?Usage: create-user.sh <first_name> <last_name> <email>}"
+EMAIL="${3:?Usage: create-user.sh <first_name> <last_name> <email>}"
 
 curl -s -X POST http://localhost:3000/user \
   -H "Content-Type: application/json" \
-  -d "{\"first_name\":\"$FIRST_NAME\",\"last_name\":\"$LAST_NAME\"}"
+  -d "{\"first_name\":\"$FIRST_NAME\",\"last_name\":\"$LAST_NAME\",\"email\":\"$EMAIL\"}"
@@ -0,0 +1,6 @@
+#!/usr/bin/env sh
+set -e
+
+USER_ID="${1:?Usage: delete-user.sh <user_id>}"
+
+curl -s -X DELETE -o /dev/null -w "%{http_code}\n" "http://localhost:3000/user/$USER_ID"
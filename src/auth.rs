@@ -0,0 +1,34 @@
+use axum::{extract::FromRequestParts, http::request::Parts};
+
+use crate::error::AppError;
+use crate::state::AppState;
+
+const ADMIN_KEY_HEADER: &str = "x-admin-key";
+
+/// Guards admin-only handlers: the `x-admin-key` header must equal `ADMIN_API_KEY`.
+/// With no key configured every admin request is rejected.
+pub struct AdminKey;
+
+impl FromRequestParts<AppState> for AdminKey {
+    type Rejection = AppError;
+
+    async fn from_request_parts(
+        parts: &mut Parts,
+        state: &AppState,
+    ) -> Result<Self, Self::Rejection> {
+        let provided = parts
+            .headers
+            .get(ADMIN_KEY_HEADER)
+            .map(|value| value.as_bytes());
+        match (state.admin_api_key.as_deref(), provided) {
+            (Some(expected), Some(provided)) if constant_time_eq(expected.as_bytes(), provided) => {
+                Ok(AdminKey)
+            }
+            _ => Err(AppError::Unauthorized(ADMIN_KEY_HEADER)),
+        }
+    }
+}
+
+pub fn constant_time_eq(a: &[u8], b: &[u8]) -> bool {
+    a.len() == b.len() && a.iter().zip(b).fold(0, |acc, (x, y)| acc | (x ^ y)) == 0
+}
@@ -1,8 +1,156 @@
+use std::sync::{
+    Arc,
+    atomic::{AtomicU64, Ordering},
+};
+use std::time::Duration;
+
 use anyhow::Context;
-use sqlx::PgPool;
+use opentelemetry::metrics::{Meter, ObservableGauge};
+use sqlx::{PgPool, Postgres, Transaction, postgres::PgPoolOptions};
+
+use crate::error::current_trace_id;
+
+pub const IDEMPOTENCY_KEY_TTL: Duration = Duration::from_secs(24 * 60 * 60);
+const IDEMPOTENCY_KEY_CLEANUP_INTERVAL: Duration = Duration::from_secs(60 * 60);
+
+pub const DEFAULT_MAX_CONNECTIONS: u32 = 10;
+pub const DEFAULT_MIN_CONNECTIONS: u32 = 1;
+/// How long startup waits for the first connection before giving up.
+const STARTUP_CONNECT_TIMEOUT: Duration = Duration::from_secs(30);
+const STARTUP_CONNECT_WARN_INTERVAL: Duration = Duration::from_secs(5);
+
+/// Connection pool settings, from the `DB_*` environment variables. A timeout left
+/// as `None` keeps the sqlx default.
+pub struct PoolOptions {
+    pub max_connections: u32,
+    pub min_connections: u32,
+    /// How long to wait for a connection, including opening a new one.
+    pub connect_timeout: Option<Duration>,
+    /// How long an unused connection is kept above `min_connections`.
+    pub idle_timeout: Option<Duration>,
+}
+
+pub async fn create_pool_with_options(
+    database_url: &str,
+    opts: PoolOptions,
+) -> anyhow::Result<PgPool> {
+    let mut options = PgPoolOptions::new()
+        .max_connections(opts.max_connections)
+        .min_connections(opts.min_connections);
+    if let Some(timeout) = opts.connect_timeout {
+        options = options.acquire_timeout(timeout);
+    }
+    if let Some(timeout) = opts.idle_timeout {
+        options = options.idle_timeout(timeout);
+    }
+    // Warn while waiting so a slow database does not look like a hung service.
+    let connect = options.connect(database_url);
+    tokio::pin!(connect);
+    let deadline = tokio::time::sleep(STARTUP_CONNECT_TIMEOUT);
+    tokio::pin!(deadline);
+    let started = tokio::time::Instant::now();
+    let mut waiting = tokio::time::interval_at(
+        started + STARTUP_CONNECT_WARN_INTERVAL,
+        STARTUP_CONNECT_WARN_INTERVAL,
+    );
+    loop {
+        tokio::select! {
+            pool = &mut connect => return pool.context("Failed to connect to DB"),
+            _ = &mut deadline => anyhow::bail!(
+                "Timed out after {}s waiting for the database to accept connections",
+                STARTUP_CONNECT_TIMEOUT.as_secs()
+            ),
+            _ = waiting.tick() => tracing::warn!(
+                waited_s = started.elapsed().as_secs(),
+                "Still waiting for the database to accept connections"
+            ),
+        }
+    }
+}
+
+/// Registers the `db.client.connections.*` gauges for `pool`. The handles are returned
+/// so the caller can keep them for as long as the pool lives. sqlx 0.8 does not expose
+/// how many tasks are waiting for a connection, so there is no `pending` gauge.
+pub fn register_pool_gauges(meter: &Meter, pool: &PgPool) -> [ObservableGauge<u64>; 2] {
+    let size_pool = pool.clone();
+    let pool_size = meter
+        .u64_observable_gauge("db.client.connections.pool_size")
+        .with_unit("{connection}")
+        .with_description("Open connections in the pool, idle or in use")
+        .with_callback(move |observer| {
+            observer.observe(size_pool.size() as u64, &[]);
+        })
+        .build();
+
+    let idle_pool = pool.clone();
+    let idle = meter
+        .u64_observable_gauge("db.client.connections.idle")
+        .with_unit("{connection}")
+        .with_description("Open connections that are not checked out")
+        .with_callback(move |observer| {
+            observer.observe(idle_pool.num_idle() as u64, &[]);
+        })
+        .build();
+
+    [pool_size, idle]
+}
+
+/// Begins a transaction tagged with the current trace id, which the `user_audit`
+/// trigger copies into every audit row written by the transaction.
+pub async fn begin_audited(pool: &PgPool) -> sqlx::Result<Transaction<'static, Postgres>> {
+    let mut tx = pool.begin().await?;
+    if let Some(trace_id) = current_trace_id() {
+        sqlx::query_scalar!("SELECT set_config('app.trace_id', $1, true)", trace_id)
+            .fetch_one(&mut *tx)
+            .await?;
+    }
+    Ok(tx)
+}
+
+pub fn spawn_idempotency_key_cleanup(pool: PgPool) {
+    tokio::spawn(async move {
+        let mut interval = tokio::time::interval(IDEMPOTENCY_KEY_CLEANUP_INTERVAL);
+        loop {
+            interval.tick().await;
+            let result = sqlx::query!(
+                "DELETE FROM idempotency_keys WHERE created_at <= NOW() - make_interval(secs => $1)",
+                IDEMPOTENCY_KEY_TTL.as_secs_f64(),
+            )
+            .execute(&pool)
+            .await;
+            match result {
+                Ok(result) => {
+                    tracing::debug!(
+                        deleted = result.rows_affected(),
+                        "Expired idempotency keys removed"
+                    )
+                }
+                Err(err) => {
+                    tracing::warn!(error = %err, "Failed to remove expired idempotency keys")
+                }
+            }
+        }
+    });
+}
 
-pub async fn create_pool(database_url: &str) -> anyhow::Result<PgPool> {
-    PgPool::connect(database_url)
-        .await
-        .context("Failed to connect to DB")
+/// Keeps `total` at the number of live users for the `app.users.total` gauge, whose
+/// callback cannot query the database itself. On failure the last value is kept.
+pub fn spawn_user_count_refresh(pool: PgPool, every: Duration, total: Arc<AtomicU64>) {
+    tokio::spawn(async move {
+        let mut interval = tokio::time::interval(every);
+        loop {
+            interval.tick().await;
+            let count = sqlx::query_scalar!(
+                r#"SELECT COUNT(*) AS "count!" FROM users WHERE deleted_at IS NULL"#
+            )
+            .fetch_one(&pool)
+            .await;
+            match count {
+                Ok(count) => total.store(count as u64, Ordering::Relaxed),
+                Err(err) => {
+                    tracing::warn!(error = %err, "Failed to count users for app.users.total")
+                }
+            }
+        }
+    });
 }
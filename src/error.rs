@@ -0,0 +1,331 @@
+#[cfg(test)]
+mod tests;
+
+use axum::{
+    Json,
+    http::{HeaderValue, StatusCode, header},
+    response::{IntoResponse, Response},
+};
+use opentelemetry::trace::{Status, TraceContextExt};
+use sqlx::error::ErrorKind;
+use tracing_opentelemetry::OpenTelemetrySpanExt;
+use uuid::Uuid;
+
+use crate::models::{ErrorBody, ErrorResponse, FieldError, UserId};
+use crate::service::ServiceError;
+
+const SERIALIZATION_FAILURE: &str = "40001";
+const DEADLOCK_DETECTED: &str = "40P01";
+const RETRY_AFTER_SECS: u64 = 1;
+
+pub enum AppError {
+    DbError(anyhow::Error),
+    NotFound {
+        resource: &'static str,
+        id: Uuid,
+    },
+    RowNotFound,
+    /// A typed path parameter did not parse; carries the offending segment.
+    InvalidId(String),
+    Validation(String),
+    InvalidFields(Vec<FieldError>),
+    /// The body could not be read or deserialized; `errors` names the field when known.
+    InvalidBody {
+        message: String,
+        errors: Vec<FieldError>,
+    },
+    UnsupportedMediaType(&'static str),
+    Conflict(&'static str),
+    ConstraintViolation,
+    /// The named credential header was missing or did not match.
+    Unauthorized(&'static str),
+    /// Authenticated, but the role does not allow the request.
+    Forbidden(String),
+    PreconditionRequired,
+    PreconditionFailed {
+        expected: i32,
+        actual: i32,
+    },
+    Unavailable {
+        source: anyhow::Error,
+        retry_after: Option<u64>,
+    },
+    Timeout,
+    PayloadTooLarge {
+        limit: usize,
+    },
+    /// No media type in `Accept` can be produced; carries the ones that can.
+    NotAcceptable(&'static [&'static str]),
+}
+
+impl AppError {
+    pub fn user_not_found(id: UserId) -> Self {
+        Self::NotFound {
+            resource: "user",
+            id: id.as_uuid(),
+        }
+    }
+
+    fn status(&self) -> StatusCode {
+        match self {
+            Self::DbError(_) => StatusCode::INTERNAL_SERVER_ERROR,
+            Self::NotFound { .. } | Self::RowNotFound => StatusCode::NOT_FOUND,
+            Self::InvalidId(_) => StatusCode::BAD_REQUEST,
+            Self::Validation(_) => StatusCode::BAD_REQUEST,
+            Self::InvalidFields(_) => StatusCode::UNPROCESSABLE_ENTITY,
+            Self::InvalidBody { .. } => StatusCode::BAD_REQUEST,
+            Self::UnsupportedMediaType(_) => StatusCode::UNSUPPORTED_MEDIA_TYPE,
+            Self::Conflict(_) | Self::ConstraintViolation => StatusCode::CONFLICT,
+            Self::Unauthorized(_) => StatusCode::UNAUTHORIZED,
+            Self::Forbidden(_) => StatusCode::FORBIDDEN,
+            Self::PreconditionRequired => StatusCode::PRECONDITION_REQUIRED,
+            Self::PreconditionFailed { .. } => StatusCode::PRECONDITION_FAILED,
+            Self::Unavailable { .. } | Self::Timeout => StatusCode::SERVICE_UNAVAILABLE,
+            Self::PayloadTooLarge { .. } => StatusCode::PAYLOAD_TOO_LARGE,
+            Self::NotAcceptable(_) => StatusCode::NOT_ACCEPTABLE,
+        }
+    }
+
+    fn code(&self) -> &'static str {
+        match self {
+            Self::DbError(_) => "internal_error",
+            Self::NotFound { .. } | Self::RowNotFound => "not_found",
+            Self::InvalidId(_) => "invalid_id",
+            Self::Validation(_) | Self::InvalidFields(_) => "validation_error",
+            Self::InvalidBody { .. } => "invalid_body",
+            Self::UnsupportedMediaType(_) => "unsupported_media_type",
+            Self::Conflict(_) | Self::ConstraintViolation => "conflict",
+            Self::Unauthorized(_) => "unauthorized",
+            Self::Forbidden(_) => "forbidden",
+            Self::PreconditionRequired => "precondition_required",
+            Self::PreconditionFailed { .. } => "precondition_failed",
+            Self::Unavailable { .. } => "service_unavailable",
+            Self::Timeout => "timeout",
+            Self::PayloadTooLarge { .. } => "payload_too_large",
+            Self::NotAcceptable(_) => "not_acceptable",
+        }
+    }
+}
+
+impl IntoResponse for AppError {
+    fn into_response(self) -> Response {
+        let status = self.status();
+        let code = self.code();
+        let mut errors = Vec::new();
+        let mut not_found = None;
+        let mut retry_after = None;
+        let mut supported = None;
+        let message = match self {
+            Self::DbError(err) => {
+                let message = format!("{err:#}");
+                tracing::error!(error = %message, "handler error");
+                tracing::Span::current().set_status(Status::error(message));
+                // Details stay in logs and traces; clients only get the trace id to quote.
+                "internal server error".to_owned()
+            }
+            Self::NotFound { resource, id } => {
+                not_found = Some((resource, id));
+                format!("{resource} {id} not found")
+            }
+            Self::RowNotFound => "resource not found".to_owned(),
+            Self::InvalidId(segment) => {
+                tracing::info!(path.segment = %segment, "invalid path parameter");
+                format!("{segment:?} is not a valid id")
+            }
+            Self::Validation(message) => {
+                tracing::info!(error = %message, "request validation failed");
+                message
+            }
+            Self::InvalidFields(fields) => {
+                let names: Vec<&str> = fields.iter().map(|f| f.field.as_str()).collect();
+                // Client misuse is recorded as a span event, not as an errored span.
+                tracing::info!(fields = ?names, "request validation failed");
+                errors = fields;
+                "request body failed validation".to_owned()
+            }
+            Self::InvalidBody {
+                message,
+                errors: fields,
+            } => {
+                tracing::info!(error = %message, "request body rejected");
+                errors = fields;
+                message
+            }
+            Self::UnsupportedMediaType(expected) => {
+                format!("Content-Type must be {expected}")
+            }
+            Self::Conflict(field) => {
+                errors.push(FieldError {
+                    field: field.to_owned(),
+                    message: "already exists".to_owned(),
+                });
+                format!("{field} already exists")
+            }
+            Self::ConstraintViolation => "request conflicts with existing data".to_owned(),
+            Self::Unauthorized(header) => format!("missing or invalid {header}"),
+            Self::Forbidden(role) => format!("role '{role}' may not perform this request"),
+            Self::PreconditionRequired => "If-Match header or version field is required".to_owned(),
+            Self::PreconditionFailed { expected, actual } => {
+                format!("version mismatch: expected {expected}, current is {actual}")
+            }
+            Self::Unavailable {
+                source,
+                retry_after: after,
+            } => {
+                let message = format!("{source:#}");
+                tracing::warn!(error = %message, "database unavailable");
+                tracing::Span::current().set_status(Status::error(message));
+                retry_after = after;
+                "service temporarily unavailable, retry later".to_owned()
+            }
+            Self::Timeout => {
+                tracing::warn!("request timed out");
+                tracing::Span::current().set_status(Status::error("request timed out"));
+                "request timed out".to_owned()
+            }
+            Self::PayloadTooLarge { limit } => {
+                format!("request body exceeds the limit of {limit} bytes")
+            }
+            Self::NotAcceptable(media_types) => {
+                supported = Some(media_types);
+                "none of the media types in Accept can be produced".to_owned()
+            }
+        };
+        let body = ErrorResponse {
+            error: ErrorBody {
+                code,
+                message,
+                trace_id: current_trace_id(),
+                resource: not_found.map(|(resource, _)| resource),
+                id: not_found.map(|(_, id)| id),
+                errors,
+                supported,
+            },
+        };
+        let mut response = (status, Json(body)).into_response();
+        if let Some(secs) = retry_after {
+            response
+                .headers_mut()
+                .insert(header::RETRY_AFTER, HeaderValue::from(secs));
+        }
+        response
+    }
+}
+
+impl<E: Into<anyhow::Error>> From<E> for AppError {
+    fn from(err: E) -> Self {
+        let err = err.into();
+        // `.context()` keeps the sqlx error reachable through downcasting.
+        let class = err
+            .downcast_ref::<sqlx::Error>()
+            .map_or(SqlxClass::Other, classify_sqlx);
+        match class {
+            SqlxClass::RowNotFound => Self::RowNotFound,
+            SqlxClass::Conflict(field) => Self::Conflict(field),
+            SqlxClass::ConstraintViolation => Self::ConstraintViolation,
+            SqlxClass::Unavailable { retry_after } => Self::Unavailable {
+                source: err,
+                retry_after,
+            },
+            SqlxClass::Other => Self::DbError(err),
+        }
+    }
+}
+
+impl From<ServiceError> for AppError {
+    fn from(err: ServiceError) -> Self {
+        match err {
+            ServiceError::InvalidFields(fields) => Self::InvalidFields(fields),
+            ServiceError::NotFound(id) => Self::user_not_found(id),
+            ServiceError::EmailTaken => Self::Conflict("email"),
+            ServiceError::VersionMismatch { expected, actual } => {
+                Self::PreconditionFailed { expected, actual }
+            }
+            ServiceError::Repository(err) => err.into(),
+        }
+    }
+}
+
+enum SqlxClass {
+    RowNotFound,
+    Conflict(&'static str),
+    ConstraintViolation,
+    Unavailable { retry_after: Option<u64> },
+    Other,
+}
+
+/// Sorts out the sqlx failures clients can act on. Constraint names and
+/// SQLSTATE codes go on the span, never into the response body.
+fn classify_sqlx(err: &sqlx::Error) -> SqlxClass {
+    match err {
+        sqlx::Error::RowNotFound => SqlxClass::RowNotFound,
+        sqlx::Error::PoolTimedOut => SqlxClass::Unavailable { retry_after: None },
+        sqlx::Error::Database(db_err) => {
+            let span = tracing::Span::current();
+            if let Some(code) = db_err.code() {
+                span.set_attribute("db.response.status_code", code.into_owned());
+            }
+            if let Some(constraint) = db_err.constraint() {
+                span.set_attribute("db.constraint", constraint.to_owned());
+            }
+            record_constraint_violation(err);
+            match db_err.kind() {
+                ErrorKind::UniqueViolation => match unique_violation_field(err) {
+                    Some(field) => SqlxClass::Conflict(field),
+                    None => SqlxClass::ConstraintViolation,
+                },
+                ErrorKind::ForeignKeyViolation => SqlxClass::ConstraintViolation,
+                _ if matches!(
+                    db_err.code().as_deref(),
+                    Some(SERIALIZATION_FAILURE | DEADLOCK_DETECTED)
+                ) =>
+                {
+                    SqlxClass::Unavailable {
+                        retry_after: Some(RETRY_AFTER_SECS),
+                    }
+                }
+                _ => SqlxClass::Other,
+            }
+        }
+        _ => SqlxClass::Other,
+    }
+}
+
+/// Adds a span event naming the constraint and SQLSTATE for unique and foreign key
+/// violations, which are answered with 409 rather than failing the span.
+pub fn record_constraint_violation(err: &sqlx::Error) {
+    if let sqlx::Error::Database(db_err) = err
+        && matches!(
+            db_err.kind(),
+            ErrorKind::UniqueViolation | ErrorKind::ForeignKeyViolation
+        )
+        && let Some(constraint) = db_err.constraint()
+    {
+        tracing::info!(
+            db.constraint = constraint,
+            db.error_code = db_err.code().as_deref(),
+            "database constraint violated"
+        );
+    }
+}
+
+pub fn unique_violation_field(err: &sqlx::Error) -> Option<&'static str> {
+    match err {
+        sqlx::Error::Database(db_err) if db_err.is_unique_violation() => {
+            match db_err.constraint() {
+                Some("users_email_key") => Some("email"),
+                _ => None,
+            }
+        }
+        _ => None,
+    }
+}
+
+pub fn current_trace_id() -> Option<String> {
+    let context = tracing::Span::current().context();
+    let span = context.span();
+    let span_context = span.span_context();
+    span_context
+        .is_valid()
+        .then(|| span_context.trace_id().to_string())
+}
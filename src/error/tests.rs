@@ -0,0 +1,352 @@
+use std::borrow::Cow;
+use std::error::Error as StdError;
+use std::fmt;
+
+use axum::{
+    body::to_bytes,
+    http::{HeaderMap, StatusCode, header},
+    response::IntoResponse,
+};
+use opentelemetry::trace::TracerProvider;
+use opentelemetry_sdk::trace::SdkTracerProvider;
+use serde_json::{Value, json};
+use sqlx::error::{DatabaseError, ErrorKind};
+use tracing_subscriber::layer::SubscriberExt;
+use uuid::Uuid;
+
+use super::AppError;
+use crate::models::FieldError;
+
+async fn render(err: AppError) -> (StatusCode, HeaderMap, Value) {
+    let response = err.into_response();
+    let status = response.status();
+    let headers = response.headers().clone();
+    let body = to_bytes(response.into_body(), usize::MAX)
+        .await
+        .expect("body");
+    (
+        status,
+        headers,
+        serde_json::from_slice(&body).expect("JSON body"),
+    )
+}
+
+/// A database error as Postgres would report it, without a server to produce one.
+#[derive(Debug)]
+struct PgError {
+    code: &'static str,
+    constraint: Option<&'static str>,
+    kind: ErrorKind,
+}
+
+impl fmt::Display for PgError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "error with SQLSTATE {}", self.code)
+    }
+}
+
+impl StdError for PgError {}
+
+impl DatabaseError for PgError {
+    fn message(&self) -> &str {
+        "database error"
+    }
+
+    fn code(&self) -> Option<Cow<'_, str>> {
+        Some(Cow::Borrowed(self.code))
+    }
+
+    fn as_error(&self) -> &(dyn StdError + Send + Sync + 'static) {
+        self
+    }
+
+    fn as_error_mut(&mut self) -> &mut (dyn StdError + Send + Sync + 'static) {
+        self
+    }
+
+    fn into_error(self: Box<Self>) -> Box<dyn StdError + Send + Sync + 'static> {
+        self
+    }
+
+    fn constraint(&self) -> Option<&str> {
+        self.constraint
+    }
+
+    fn kind(&self) -> ErrorKind {
+        match self.kind {
+            ErrorKind::UniqueViolation => ErrorKind::UniqueViolation,
+            ErrorKind::ForeignKeyViolation => ErrorKind::ForeignKeyViolation,
+            _ => ErrorKind::Other,
+        }
+    }
+}
+
+fn pg_error(code: &'static str, constraint: Option<&'static str>, kind: ErrorKind) -> AppError {
+    let err = sqlx::Error::Database(Box::new(PgError {
+        code,
+        constraint,
+        kind,
+    }));
+    // Handlers see sqlx errors wrapped in context, as the repository returns them.
+    AppError::from(anyhow::Error::new(err).context("Failed to insert user"))
+}
+
+#[tokio::test]
+async fn a_taken_email_is_a_409_naming_the_field() {
+    let err = pg_error("23505", Some("users_email_key"), ErrorKind::UniqueViolation);
+
+    let (status, _, body) = render(err).await;
+
+    assert_eq!(status, StatusCode::CONFLICT);
+    assert_eq!(body["error"]["code"], "conflict");
+    assert_eq!(body["error"]["errors"][0]["field"], "email");
+}
+
+#[tokio::test]
+async fn other_unique_violations_are_a_409_without_the_constraint_name() {
+    let err = pg_error("23505", Some("users_pkey"), ErrorKind::UniqueViolation);
+
+    let (status, _, body) = render(err).await;
+
+    assert_eq!(status, StatusCode::CONFLICT);
+    assert!(!body.to_string().contains("users_pkey"), "{body}");
+}
+
+#[tokio::test]
+async fn a_foreign_key_violation_is_a_409() {
+    let err = pg_error(
+        "23503",
+        Some("addresses_user_id_fkey"),
+        ErrorKind::ForeignKeyViolation,
+    );
+
+    let (status, _, body) = render(err).await;
+
+    assert_eq!(status, StatusCode::CONFLICT);
+    assert_eq!(body["error"]["code"], "conflict");
+    assert!(
+        !body.to_string().contains("addresses_user_id_fkey"),
+        "{body}"
+    );
+}
+
+#[tokio::test]
+async fn a_missing_row_is_a_404() {
+    let err = AppError::from(
+        anyhow::Error::new(sqlx::Error::RowNotFound).context("Failed to fetch user"),
+    );
+
+    let (status, _, body) = render(err).await;
+
+    assert_eq!(status, StatusCode::NOT_FOUND);
+    assert_eq!(body["error"]["code"], "not_found");
+}
+
+#[tokio::test]
+async fn a_pool_timeout_is_a_503_without_retry_after() {
+    let (status, headers, body) = render(AppError::from(sqlx::Error::PoolTimedOut)).await;
+
+    assert_eq!(status, StatusCode::SERVICE_UNAVAILABLE);
+    assert_eq!(body["error"]["code"], "service_unavailable");
+    assert!(!headers.contains_key(header::RETRY_AFTER));
+}
+
+#[tokio::test]
+async fn serialization_failures_and_deadlocks_are_a_503_with_retry_after() {
+    for code in ["40001", "40P01"] {
+        let (status, headers, _) = render(pg_error(code, None, ErrorKind::Other)).await;
+
+        assert_eq!(status, StatusCode::SERVICE_UNAVAILABLE, "{code}");
+        assert_eq!(headers[header::RETRY_AFTER], "1", "{code}");
+    }
+}
+
+#[tokio::test]
+async fn other_database_errors_are_a_500() {
+    let (status, _, body) = render(pg_error("42P01", None, ErrorKind::Other)).await;
+
+    assert_eq!(status, StatusCode::INTERNAL_SERVER_ERROR);
+    assert_eq!(body["error"]["code"], "internal_error");
+}
+
+#[tokio::test]
+async fn every_error_is_a_json_envelope_with_its_status_and_code() {
+    let cases = [
+        (
+            AppError::DbError(anyhow::anyhow!("boom")),
+            StatusCode::INTERNAL_SERVER_ERROR,
+            "internal_error",
+        ),
+        (
+            AppError::NotFound {
+                resource: "address",
+                id: Uuid::nil(),
+            },
+            StatusCode::NOT_FOUND,
+            "not_found",
+        ),
+        (AppError::RowNotFound, StatusCode::NOT_FOUND, "not_found"),
+        (
+            AppError::InvalidId("abc".to_owned()),
+            StatusCode::BAD_REQUEST,
+            "invalid_id",
+        ),
+        (
+            AppError::Validation("limit must be positive".to_owned()),
+            StatusCode::BAD_REQUEST,
+            "validation_error",
+        ),
+        (
+            AppError::InvalidFields(vec![FieldError {
+                field: "email".to_owned(),
+                message: "must not be blank".to_owned(),
+            }]),
+            StatusCode::UNPROCESSABLE_ENTITY,
+            "validation_error",
+        ),
+        (
+            AppError::InvalidBody {
+                message: "expected a JSON object".to_owned(),
+                errors: Vec::new(),
+            },
+            StatusCode::BAD_REQUEST,
+            "invalid_body",
+        ),
+        (
+            AppError::UnsupportedMediaType("application/json"),
+            StatusCode::UNSUPPORTED_MEDIA_TYPE,
+            "unsupported_media_type",
+        ),
+        (
+            AppError::Conflict("email"),
+            StatusCode::CONFLICT,
+            "conflict",
+        ),
+        (
+            AppError::ConstraintViolation,
+            StatusCode::CONFLICT,
+            "conflict",
+        ),
+        (
+            AppError::Unauthorized("x-api-key"),
+            StatusCode::UNAUTHORIZED,
+            "unauthorized",
+        ),
+        (
+            AppError::Forbidden("reader".to_owned()),
+            StatusCode::FORBIDDEN,
+            "forbidden",
+        ),
+        (
+            AppError::PreconditionRequired,
+            StatusCode::PRECONDITION_REQUIRED,
+            "precondition_required",
+        ),
+        (
+            AppError::PreconditionFailed {
+                expected: 1,
+                actual: 2,
+            },
+            StatusCode::PRECONDITION_FAILED,
+            "precondition_failed",
+        ),
+        (
+            AppError::Unavailable {
+                source: anyhow::anyhow!("pool timed out"),
+                retry_after: None,
+            },
+            StatusCode::SERVICE_UNAVAILABLE,
+            "service_unavailable",
+        ),
+        (
+            AppError::Timeout,
+            StatusCode::SERVICE_UNAVAILABLE,
+            "timeout",
+        ),
+        (
+            AppError::PayloadTooLarge { limit: 4096 },
+            StatusCode::PAYLOAD_TOO_LARGE,
+            "payload_too_large",
+        ),
+        (
+            AppError::NotAcceptable(&["application/json"]),
+            StatusCode::NOT_ACCEPTABLE,
+            "not_acceptable",
+        ),
+    ];
+
+    for (err, expected_status, expected_code) in cases {
+        let response = err.into_response();
+        assert_eq!(
+            response.headers()[header::CONTENT_TYPE],
+            "application/json",
+            "{expected_code}"
+        );
+        let status = response.status();
+        let body: Value = serde_json::from_slice(
+            &to_bytes(response.into_body(), usize::MAX)
+                .await
+                .expect("body"),
+        )
+        .expect("JSON body");
+
+        assert_eq!(status, expected_status, "{expected_code}");
+        assert_eq!(body["error"]["code"], expected_code);
+        assert!(body["error"]["message"].is_string(), "{expected_code}");
+    }
+}
+
+#[tokio::test]
+async fn a_not_found_names_the_resource_and_id() {
+    let id = Uuid::new_v4();
+    let err = AppError::NotFound {
+        resource: "address",
+        id,
+    };
+
+    let (_, _, body) = render(err).await;
+
+    assert_eq!(body["error"]["resource"], "address");
+    assert_eq!(body["error"]["id"], json!(id.to_string()));
+    assert_eq!(body["error"]["message"], format!("address {id} not found"));
+}
+
+#[tokio::test]
+async fn internal_errors_hide_their_text() {
+    let err = AppError::DbError(anyhow::anyhow!(
+        "password authentication failed for user \"appuser\""
+    ));
+
+    let (_, _, body) = render(err).await;
+
+    assert_eq!(body["error"]["message"], "internal server error");
+    assert!(!body.to_string().contains("appuser"), "{body}");
+}
+
+#[tokio::test]
+async fn errors_carry_the_trace_id_of_the_current_span() {
+    let provider = SdkTracerProvider::builder().build();
+    let subscriber = tracing_subscriber::registry()
+        .with(tracing_opentelemetry::layer().with_tracer(provider.tracer("test")));
+
+    let (response, trace_id) = tracing::subscriber::with_default(subscriber, || {
+        let _span = tracing::info_span!("request").entered();
+        let trace_id = super::current_trace_id().expect("a sampled span");
+        (AppError::Timeout.into_response(), trace_id)
+    });
+    let body: Value = serde_json::from_slice(
+        &to_bytes(response.into_body(), usize::MAX)
+            .await
+            .expect("body"),
+    )
+    .expect("JSON body");
+
+    assert_eq!(body["error"]["trace_id"], trace_id);
+}
+
+#[tokio::test]
+async fn errors_outside_a_trace_leave_out_the_trace_id() {
+    let (_, _, body) = render(AppError::Timeout).await;
+
+    assert!(body["error"].get("trace_id").is_none(), "{body}");
+}
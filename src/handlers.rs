@@ -1,107 +1,1157 @@
+mod addresses;
+mod audit;
+mod avatar;
+mod stats;
+#[cfg(test)]
+mod tests;
+
+use std::{
+    borrow::Cow,
+    collections::{HashMap, HashSet},
+    io,
+    time::Instant,
+};
+
 use anyhow::Context;
 use axum::{
     Json,
-    extract::{Path, State},
-    http::StatusCode,
+    body::{Body, Bytes},
+    extract::{Query, State},
+    http::{HeaderMap, HeaderName, HeaderValue, StatusCode, header},
     response::{IntoResponse, Response},
 };
-use sqlx::Row;
+use chrono::{DateTime, TimeDelta, Utc};
+use futures_util::StreamExt;
+use opentelemetry::{KeyValue, trace::TraceContextExt};
+use serde::Serialize;
+use tokio::sync::mpsc;
+use tokio_stream::wrappers::ReceiverStream;
 use tracing::{Instrument, instrument};
+use tracing_opentelemetry::OpenTelemetrySpanExt;
 use uuid::Uuid;
 
-use crate::models::{CreateUserRequest, User};
+use crate::auth::AdminKey;
+use crate::error::{AppError, current_trace_id};
+use crate::models::{
+    BulkDeleteResponse, CountResponse, CreateUserRequest, CursorPagedResponse, CursorPagination,
+    DeletedFilter, Envelope, EnvelopeBody, EnvelopeMeta, ExistsParams, ExistsResponse,
+    FieldsParams, IncludeParams, JsonBody, JsonOrForm, LookupResponse, MergeUsersRequest,
+    PagedResponse, PaginationParams, PatchUserRequest, RecentParams, SearchParams, SortParams,
+    UpdateUserRequest, User, UserFilter, UserId, UserView, UserWithAddresses,
+};
+use crate::otel;
+use crate::pagination::{decode_cursor, encode_cursor};
+use crate::repository::{MergedUsers, UserChanges, UserQuery};
+use crate::service::ServiceError;
 use crate::state::AppState;
 
-pub struct AppError(anyhow::Error);
+pub use addresses::{add_address, delete_address, get_addresses};
+pub use audit::get_user_audit;
+pub use avatar::{MAX_AVATAR_BYTES, get_avatar, put_avatar};
+pub use stats::get_user_stats;
+
+const MAX_SPAN_FILTER_LEN: usize = 32;
+const MIN_SEARCH_QUERY_LEN: usize = 2;
+const DEFAULT_RECENT_WINDOW_HOURS: i64 = 24;
+const EXPORT_CHANNEL_CAPACITY: usize = 64;
+const IDEMPOTENCY_KEY_HEADER: &str = "idempotency-key";
+pub const MAX_LOOKUP_IDS: usize = 200;
+const MAX_EXISTS_IDS: u32 = 10;
+const X_TOTAL_COUNT: HeaderName = HeaderName::from_static("x-total-count");
+const PREFERENCE_APPLIED: HeaderName = HeaderName::from_static("preference-applied");
+
+fn record_db_duration(state: &AppState, operation: &'static str, start: Instant) {
+    record_db_duration_in(state, "users", operation, start);
+}
+
+/// A response body that can be split into the `data` and `meta` of the envelope.
+trait Enveloped: Serialize {
+    type Data: Serialize;
+
+    fn into_parts(self) -> (Self::Data, EnvelopeMeta);
+}
+
+impl<T: Serialize> Enveloped for PagedResponse<T> {
+    type Data = Vec<T>;
+
+    fn into_parts(self) -> (Vec<T>, EnvelopeMeta) {
+        let meta = EnvelopeMeta {
+            count: Some(self.items.len()),
+            total_count: self.total_count,
+            limit: Some(self.limit),
+            offset: Some(self.offset),
+            ..EnvelopeMeta::default()
+        };
+        (self.items, meta)
+    }
+}
+
+impl<T: Serialize> Enveloped for CursorPagedResponse<T> {
+    type Data = Vec<T>;
+
+    fn into_parts(self) -> (Vec<T>, EnvelopeMeta) {
+        let meta = EnvelopeMeta {
+            count: Some(self.items.len()),
+            next_cursor: self.next_cursor,
+            ..EnvelopeMeta::default()
+        };
+        (self.items, meta)
+    }
+}
+
+impl Enveloped for LookupResponse {
+    type Data = Vec<User>;
+
+    fn into_parts(self) -> (Vec<User>, EnvelopeMeta) {
+        let meta = EnvelopeMeta {
+            count: Some(self.items.len()),
+            missing: Some(self.missing),
+            ..EnvelopeMeta::default()
+        };
+        (self.items, meta)
+    }
+}
+
+impl<T: Serialize> Enveloped for Vec<T> {
+    type Data = Self;
+
+    fn into_parts(self) -> (Self, EnvelopeMeta) {
+        let meta = EnvelopeMeta {
+            count: Some(self.len()),
+            ..EnvelopeMeta::default()
+        };
+        (self, meta)
+    }
+}
+
+macro_rules! enveloped_object {
+    ($($ty:ty),*) => {
+        $(impl Enveloped for $ty {
+            type Data = Self;
+
+            fn into_parts(self) -> (Self, EnvelopeMeta) {
+                (self, EnvelopeMeta::default())
+            }
+        })*
+    };
+}
+
+enveloped_object!(User, UserView, UserWithAddresses);
 
-impl IntoResponse for AppError {
-    fn into_response(self) -> Response {
-        (StatusCode::INTERNAL_SERVER_ERROR, self.0.to_string()).into_response()
+/// The JSON body for every user handler: `body` as is, or split into `data` and `meta`
+/// with the current trace id when [`Envelope`] is on.
+fn respond<T: Enveloped>(envelope: Envelope, body: T) -> Response {
+    if !envelope.0 {
+        return Json(body).into_response();
     }
+    let (data, mut meta) = body.into_parts();
+    meta.trace_id = current_trace_id();
+    Json(EnvelopeBody { data, meta }).into_response()
 }
 
-impl<E: Into<anyhow::Error>> From<E> for AppError {
-    fn from(err: E) -> Self {
-        Self(err.into())
+fn record_db_duration_in(
+    state: &AppState,
+    collection: &'static str,
+    operation: &'static str,
+    start: Instant,
+) {
+    otel::record_db_operation(&state.db_operation_duration, collection, operation, start);
+}
+
+fn conflict(state: &AppState, field: &'static str) -> AppError {
+    state
+        .users_conflict_counter
+        .add(1, &[KeyValue::new("field", field)]);
+    AppError::Conflict(field)
+}
+
+/// The `limit` a listing endpoint runs with under the configured [`PageSize`].
+///
+/// [`PageSize`]: crate::pagination::PageSize
+fn page_limit(state: &AppState, limit: Option<u32>) -> Result<u32, AppError> {
+    let max = state.page_size.max;
+    state.page_size.effective(limit).ok_or_else(|| {
+        AppError::Validation(if limit == Some(0) {
+            "limit must be at least 1".to_owned()
+        } else {
+            format!("limit must not exceed {max}")
+        })
+    })
+}
+
+fn truncate_for_span(value: &str) -> String {
+    value.chars().take(MAX_SPAN_FILTER_LEN).collect()
+}
+
+fn record_selected_fields(fields: Option<&[&str]>) {
+    if let Some(fields) = fields {
+        tracing::Span::current().record("fields", fields.join(","));
     }
 }
 
-#[instrument(skip(state))]
+#[allow(clippy::too_many_arguments)]
+#[instrument(
+    skip(state, pagination, filter, deleted, sort, fields, envelope),
+    fields(
+        filter.last_name = filter.last_name.as_deref().map(truncate_for_span),
+        include_deleted = deleted.include_deleted,
+        fields = tracing::field::Empty,
+        result.total_count = tracing::field::Empty,
+        result.limit = tracing::field::Empty,
+        result.page_size = tracing::field::Empty,
+        response.media_type = tracing::field::Empty,
+    )
+)]
 pub async fn get_users(
     State(state): State<AppState>,
-) -> Result<impl IntoResponse, AppError> {
-    let rows = sqlx::query("SELECT id, first_name, last_name FROM users")
-        .fetch_all(&state.db)
-        .instrument(tracing::info_span!("db.query", db.statement = "SELECT users"))
-        .await
-        .context("Failed to fetch users")?;
-
-    let users: Vec<User> = {
-        let _span = tracing::info_span!("result.map", row_count = rows.len()).entered();
-        rows.iter()
-            .map(|row| User {
-                id: row.get("id"),
-                first_name: row.get("first_name"),
-                last_name: row.get("last_name"),
-            })
-            .collect()
+    Query(pagination): Query<PaginationParams>,
+    Query(filter): Query<UserFilter>,
+    Query(deleted): Query<DeletedFilter>,
+    Query(sort): Query<SortParams>,
+    Query(fields): Query<FieldsParams>,
+    envelope: Envelope,
+    headers: HeaderMap,
+) -> Result<Response, AppError> {
+    let representation = UsersRepresentation::negotiate(&headers)?;
+    tracing::Span::current().record("response.media_type", representation.media_type());
+    let fields = fields.selection()?;
+    record_selected_fields(fields.as_deref());
+    let limit = page_limit(&state, pagination.limit)?;
+    let offset = pagination.offset.unwrap_or(0);
+    if filter.last_name.as_deref() == Some("") {
+        return Err(AppError::Validation(
+            "last_name must not be empty".to_owned(),
+        ));
+    }
+    if let Some(cursor) = pagination.cursor.as_deref() {
+        if representation == UsersRepresentation::Csv {
+            return Err(AppError::Validation(
+                "cursor pages are only available as JSON".to_owned(),
+            ));
+        }
+        return match decode_cursor(cursor) {
+            Ok(after) => {
+                fetch_users_page(
+                    &state,
+                    Some(after),
+                    limit,
+                    &filter,
+                    deleted.include_deleted,
+                    fields.as_deref(),
+                    envelope,
+                )
+                .await
+            }
+            Err(err) => Err(AppError::Validation(err.to_string())),
+        };
+    }
+
+    // The total comes back with the page, unless the client opted out of counting
+    // with `Prefer: count=none`.
+    let with_count = !prefers_no_count(&headers);
+    let query = UserQuery {
+        limit,
+        offset,
+        last_name: filter.last_name.as_deref(),
+        include_deleted: deleted.include_deleted,
+        sort: sort.sort,
+        order: sort.order,
+        with_count,
     };
 
-    Ok(Json(users))
+    let page = state.users.list_users(&query).await?;
+    let total_count = page.total_count;
+
+    let span = tracing::Span::current();
+    if let Some(total_count) = total_count {
+        span.record("result.total_count", total_count);
+    }
+    span.record("result.limit", limit);
+    span.record("result.page_size", page.users.len());
+
+    let mut response = match representation {
+        UsersRepresentation::Json => {
+            let users: Vec<UserView> = {
+                let _span =
+                    tracing::info_span!("result.map", row_count = page.users.len()).entered();
+                page.users
+                    .into_iter()
+                    .map(|user| UserView::new(user, fields.as_deref()))
+                    .collect()
+            };
+            respond(
+                envelope,
+                PagedResponse {
+                    total_count,
+                    limit,
+                    offset,
+                    items: users,
+                },
+            )
+        }
+        // CSV has fixed columns, so `fields` and the envelope do not apply; paging
+        // details are only carried by X-Total-Count.
+        UsersRepresentation::Csv => {
+            let _span = tracing::info_span!("result.map", row_count = page.users.len()).entered();
+            let mut body = CSV_HEADER.to_vec();
+            for user in &page.users {
+                body.extend(csv_line(user).context("Failed to encode users as CSV")?);
+            }
+            ([(header::CONTENT_TYPE, "text/csv; charset=utf-8")], body).into_response()
+        }
+    };
+    response
+        .headers_mut()
+        .insert(header::VARY, HeaderValue::from_static("accept"));
+    if let Some(total_count) = total_count {
+        response
+            .headers_mut()
+            .insert(X_TOTAL_COUNT, HeaderValue::from(total_count));
+    }
+    Ok(response)
 }
 
-#[instrument(skip(state), fields(user_id = %id))]
-pub async fn get_user(
+/// Representations `GET /users` can be served as, in order of preference.
+#[derive(Clone, Copy, PartialEq)]
+enum UsersRepresentation {
+    Json,
+    Csv,
+}
+
+impl UsersRepresentation {
+    const ALL: [Self; 2] = [Self::Json, Self::Csv];
+    const MEDIA_TYPES: &'static [&'static str] = &["application/json", "text/csv"];
+
+    fn media_type(self) -> &'static str {
+        match self {
+            Self::Json => "application/json",
+            Self::Csv => "text/csv",
+        }
+    }
+
+    /// Picks the representation with the highest q-value in `Accept`, each taking the
+    /// q of the most specific range that matches it (`text/csv` over `text/*` over `*/*`).
+    /// Ties go to JSON, as does a missing header. No acceptable match is a 406.
+    fn negotiate(headers: &HeaderMap) -> Result<Self, AppError> {
+        let ranges: Vec<(&str, f32)> = headers
+            .get_all(header::ACCEPT)
+            .iter()
+            .filter_map(|value| value.to_str().ok())
+            .flat_map(|value| value.split(','))
+            .filter_map(parse_media_range)
+            .collect();
+        if ranges.is_empty() {
+            return Ok(Self::Json);
+        }
+        let quality = |representation: Self| {
+            let media_type = representation.media_type();
+            let (main_type, _) = media_type.split_once('/').unwrap_or_default();
+            ranges
+                .iter()
+                .filter_map(|&(range, q)| {
+                    let specificity = if range.eq_ignore_ascii_case(media_type) {
+                        2
+                    } else if range
+                        .strip_suffix("/*")
+                        .is_some_and(|t| t.eq_ignore_ascii_case(main_type))
+                    {
+                        1
+                    } else if range == "*/*" {
+                        0
+                    } else {
+                        return None;
+                    };
+                    Some((specificity, q))
+                })
+                .max_by_key(|&(specificity, _)| specificity)
+                .map_or(0.0, |(_, q)| q)
+        };
+        let mut best = None;
+        for representation in Self::ALL {
+            let q = quality(representation);
+            if q > 0.0 && best.is_none_or(|(_, best_q)| q > best_q) {
+                best = Some((representation, q));
+            }
+        }
+        best.map(|(representation, _)| representation)
+            .ok_or(AppError::NotAcceptable(Self::MEDIA_TYPES))
+    }
+}
+
+/// A media range and its q-value from one `Accept` entry; entries with a malformed
+/// q-value are dropped.
+fn parse_media_range(entry: &str) -> Option<(&str, f32)> {
+    let mut parts = entry.split(';').map(str::trim);
+    let range = parts.next().filter(|range| range.contains('/'))?;
+    let mut q = 1.0;
+    for param in parts {
+        if let Some((name, value)) = param.split_once('=')
+            && name.trim().eq_ignore_ascii_case("q")
+        {
+            q = value
+                .trim()
+                .parse()
+                .ok()
+                .filter(|q| (0.0..=1.0).contains(q))?;
+        }
+    }
+    Some((range, q))
+}
+
+/// Whether one of the `Prefer` headers lists `preference`, e.g. `count=none`.
+/// Preferences the server does not know are ignored, as RFC 7240 asks.
+fn prefers(headers: &HeaderMap, preference: &str) -> bool {
+    headers
+        .get_all("prefer")
+        .iter()
+        .filter_map(|value| value.to_str().ok())
+        .flat_map(|value| value.split(','))
+        .any(|listed| listed.trim().eq_ignore_ascii_case(preference))
+}
+
+fn prefers_no_count(headers: &HeaderMap) -> bool {
+    prefers(headers, "count=none")
+}
+
+/// Whether live users already have this name, so a client can warn about a likely
+/// duplicate before creating one. Names are personal data, so only the count is traced.
+#[instrument(skip(state, params), fields(result.count = tracing::field::Empty))]
+pub async fn users_exist(
     State(state): State<AppState>,
-    Path(id): Path<Uuid>,
+    Query(params): Query<ExistsParams>,
 ) -> Result<Response, AppError> {
-    let row = sqlx::query("SELECT id, first_name, last_name FROM users WHERE id = $1")
-        .bind(id)
-        .fetch_optional(&state.db)
-        .instrument(tracing::info_span!("db.query", db.statement = "SELECT user BY id"))
-        .await
-        .context("Failed to fetch user")?;
+    let required = |value: Option<String>, field: &str| {
+        value
+            .filter(|value| !value.trim().is_empty())
+            .ok_or_else(|| AppError::Validation(format!("{field} is required")))
+    };
+    let first_name = required(params.first_name, "first_name")?;
+    let last_name = required(params.last_name, "last_name")?;
 
-    let _span = tracing::info_span!("result.build").entered();
-    match row {
-        Some(row) => {
-            let user = User {
-                id: row.get("id"),
-                first_name: row.get("first_name"),
-                last_name: row.get("last_name"),
+    let matches = state
+        .users
+        .find_by_name(first_name.trim(), last_name.trim(), MAX_EXISTS_IDS)
+        .await?;
+    tracing::Span::current().record("result.count", matches.count);
+
+    Ok(Json(ExistsResponse {
+        exists: matches.count > 0,
+        count: matches.count,
+        ids: matches.ids,
+    })
+    .into_response())
+}
+
+#[instrument(
+    skip(state, filter),
+    fields(filter.last_name = filter.last_name.as_deref().map(truncate_for_span))
+)]
+pub async fn count_users(
+    State(state): State<AppState>,
+    Query(filter): Query<UserFilter>,
+) -> Result<Response, AppError> {
+    if filter.last_name.as_deref() == Some("") {
+        return Err(AppError::Validation(
+            "last_name must not be empty".to_owned(),
+        ));
+    }
+
+    let count = state
+        .users
+        .count_users(filter.last_name.as_deref(), false)
+        .await?;
+
+    Ok(Json(CountResponse { count }).into_response())
+}
+
+#[instrument(skip(state, pagination, filter, envelope))]
+pub async fn get_users_page(
+    State(state): State<AppState>,
+    Query(pagination): Query<CursorPagination>,
+    Query(filter): Query<UserFilter>,
+    envelope: Envelope,
+) -> Result<Response, AppError> {
+    let limit = page_limit(&state, pagination.limit)?;
+    if filter.last_name.as_deref() == Some("") {
+        return Err(AppError::Validation(
+            "last_name must not be empty".to_owned(),
+        ));
+    }
+    let after = match pagination.cursor.as_deref().map(decode_cursor).transpose() {
+        Ok(after) => after,
+        Err(err) => return Err(AppError::Validation(err.to_string())),
+    };
+
+    fetch_users_page(&state, after, limit, &filter, false, None, envelope).await
+}
+
+#[instrument(
+    skip(state, filter, envelope),
+    fields(cursor = ?after, page_size = tracing::field::Empty)
+)]
+async fn fetch_users_page(
+    state: &AppState,
+    after: Option<UserId>,
+    limit: u32,
+    filter: &UserFilter,
+    include_deleted: bool,
+    fields: Option<&[&str]>,
+    envelope: Envelope,
+) -> Result<Response, AppError> {
+    let (users, has_more) = state
+        .users
+        .list_users_after(after, limit, filter.last_name.as_deref(), include_deleted)
+        .await?;
+    tracing::Span::current().record("page_size", users.len());
+
+    let page = {
+        let _span = tracing::info_span!("result.map", row_count = users.len()).entered();
+        // The cursor is taken before projection, since `id` may not be among the selected fields.
+        let next_cursor = if has_more {
+            users.last().map(|user| encode_cursor(user.id))
+        } else {
+            None
+        };
+        let items: Vec<UserView> = users
+            .into_iter()
+            .map(|user| UserView::new(user, fields))
+            .collect();
+        CursorPagedResponse { items, next_cursor }
+    };
+
+    Ok(respond(envelope, page))
+}
+
+#[instrument(
+    skip(state),
+    fields(rows_streamed = tracing::field::Empty, bytes_streamed = tracing::field::Empty)
+)]
+pub async fn export_users(State(state): State<AppState>) -> Response {
+    let (tx, rx) = mpsc::channel(EXPORT_CHANNEL_CAPACITY);
+    // The spawned task carries the handler span, so it closes once streaming ends.
+    tokio::spawn(stream_users(state, tx, None, ndjson_line).in_current_span());
+
+    (
+        [(header::CONTENT_TYPE, "application/x-ndjson")],
+        Body::from_stream(ReceiverStream::new(rx)),
+    )
+        .into_response()
+}
+
+#[instrument(
+    skip(state),
+    fields(rows_streamed = tracing::field::Empty, bytes_streamed = tracing::field::Empty)
+)]
+pub async fn export_users_csv(State(state): State<AppState>) -> Response {
+    let (tx, rx) = mpsc::channel(EXPORT_CHANNEL_CAPACITY);
+    tokio::spawn(stream_users(state, tx, Some(CSV_HEADER), csv_line).in_current_span());
+
+    (
+        [
+            (header::CONTENT_TYPE, "text/csv; charset=utf-8"),
+            (
+                header::CONTENT_DISPOSITION,
+                "attachment; filename=\"users.csv\"",
+            ),
+        ],
+        Body::from_stream(ReceiverStream::new(rx)),
+    )
+        .into_response()
+}
+
+fn ndjson_line(user: &User) -> io::Result<Vec<u8>> {
+    let mut line = serde_json::to_vec(user).map_err(io::Error::other)?;
+    line.push(b'\n');
+    Ok(line)
+}
+
+const CSV_HEADER: &[u8] = b"id,first_name,last_name\r\n";
+
+fn csv_line(user: &User) -> io::Result<Vec<u8>> {
+    Ok(format!(
+        "{},{},{}\r\n",
+        user.id,
+        csv_field(&user.first_name),
+        csv_field(&user.last_name)
+    )
+    .into_bytes())
+}
+
+/// Quotes a field per RFC 4180 when it holds a comma, quote or line break.
+fn csv_field(value: &str) -> Cow<'_, str> {
+    if value.contains([',', '"', '\n', '\r']) {
+        Cow::Owned(format!("\"{}\"", value.replace('"', "\"\"")))
+    } else {
+        Cow::Borrowed(value)
+    }
+}
+
+async fn stream_users(
+    state: AppState,
+    tx: mpsc::Sender<Result<Bytes, io::Error>>,
+    header: Option<&'static [u8]>,
+    encode: fn(&User) -> io::Result<Vec<u8>>,
+) {
+    let start = Instant::now();
+    let (rows_streamed, bytes_streamed) = async {
+        let mut rows_streamed: u64 = 0;
+        let mut bytes_streamed: u64 = 0;
+        if let Some(header) = header {
+            if tx.send(Ok(Bytes::from_static(header))).await.is_err() {
+                return (rows_streamed, bytes_streamed);
+            }
+            bytes_streamed += header.len() as u64;
+        }
+        let mut users = state.users.stream_users();
+        while let Some(user) = users.next().await {
+            let line = match user {
+                Ok(user) => encode(&user).map(Bytes::from),
+                Err(err) => Err(io::Error::other(err)),
             };
-            Ok((StatusCode::OK, Json(Some(user))).into_response())
+
+            match line {
+                Ok(line) => {
+                    let len = line.len() as u64;
+                    if tx.send(Ok(line)).await.is_err() {
+                        tracing::info!("Client disconnected during user export");
+                        break;
+                    }
+                    rows_streamed += 1;
+                    bytes_streamed += len;
+                }
+                Err(err) => {
+                    let trace_id = tracing::Span::current()
+                        .context()
+                        .span()
+                        .span_context()
+                        .trace_id();
+                    tracing::error!(%trace_id, error = %err, "Failed to stream users");
+                    let _ = tx.send(Err(err)).await;
+                    break;
+                }
+            }
         }
-        None => Ok(StatusCode::NOT_FOUND.into_response()),
+        (rows_streamed, bytes_streamed)
     }
+    .instrument(tracing::info_span!(
+        "db.query",
+        db.statement = "SELECT users STREAM"
+    ))
+    .await;
+    record_db_duration(&state, "SELECT", start);
+
+    let span = tracing::Span::current();
+    span.record("rows_streamed", rows_streamed);
+    span.record("bytes_streamed", bytes_streamed);
 }
 
-#[instrument(skip(state, body), fields(user_first_name = %body.first_name))]
+#[instrument(
+    skip(state, params, pagination, envelope),
+    fields(
+        query_len = tracing::field::Empty,
+        search.query_terms = tracing::field::Empty,
+        result.total_count = tracing::field::Empty,
+    )
+)]
+pub async fn search_users(
+    State(state): State<AppState>,
+    Query(params): Query<SearchParams>,
+    Query(pagination): Query<PaginationParams>,
+    envelope: Envelope,
+) -> Result<Response, AppError> {
+    let q = params.q.as_deref().map(str::trim).unwrap_or_default();
+    let span = tracing::Span::current();
+    span.record("query_len", q.chars().count());
+    span.record("search.query_terms", q.split_whitespace().count());
+    if q.chars().count() < MIN_SEARCH_QUERY_LEN {
+        return Err(AppError::Validation(format!(
+            "q must be at least {MIN_SEARCH_QUERY_LEN} characters"
+        )));
+    }
+    let limit = page_limit(&state, pagination.limit)?;
+    let offset = pagination.offset.unwrap_or(0);
+    if pagination.cursor.is_some() {
+        return Err(AppError::Validation(
+            "cursor is not supported for search".to_owned(),
+        ));
+    }
+
+    let users = state.users.search_users(q, limit, offset).await?;
+    let total_count = state.users.count_search_results(q).await?;
+    span.record("result.total_count", total_count);
+
+    Ok(respond(
+        envelope,
+        PagedResponse {
+            total_count: Some(total_count),
+            limit,
+            offset,
+            items: users,
+        },
+    ))
+}
+
+/// Users created at or after `since`, newest first. A `since` in the future is not an error,
+/// it just matches nothing.
+#[instrument(skip(state, params, envelope), fields(recent.window_seconds = tracing::field::Empty, result.count = tracing::field::Empty))]
+pub async fn get_recent_users(
+    State(state): State<AppState>,
+    Query(params): Query<RecentParams>,
+    envelope: Envelope,
+) -> Result<Response, AppError> {
+    let now = Utc::now();
+    let since = match params.since.as_deref() {
+        Some(since) => DateTime::parse_from_rfc3339(since)
+            .map_err(|err| {
+                AppError::Validation(format!("since must be an RFC 3339 timestamp: {err}"))
+            })?
+            .with_timezone(&Utc),
+        None => now - TimeDelta::hours(DEFAULT_RECENT_WINDOW_HOURS),
+    };
+    let limit = page_limit(&state, params.limit)?;
+    let span = tracing::Span::current();
+    span.record("recent.window_seconds", (now - since).num_seconds());
+
+    let users = state.users.recent_users(since, limit).await?;
+    span.record("result.count", users.len());
+
+    Ok(respond(envelope, users))
+}
+
+fn user_etag(user: &User) -> String {
+    // version is bumped by a trigger on every change to the row.
+    format!("W/\"{}\"", user.version)
+}
+
+fn opaque_tag(tag: &str) -> Option<&str> {
+    let tag = tag.trim();
+    let tag = tag.strip_prefix("W/").unwrap_or(tag);
+    tag.strip_prefix('"')?.strip_suffix('"')
+}
+
+/// Weak comparison of an `If-None-Match` list against `etag`. Malformed entries never match.
+fn if_none_match_matches(header: &str, etag: &str) -> bool {
+    if header.trim() == "*" {
+        return true;
+    }
+    let Some(expected) = opaque_tag(etag) else {
+        return false;
+    };
+    header
+        .split(',')
+        .any(|tag| opaque_tag(tag) == Some(expected))
+}
+
+#[instrument(
+    skip(state, deleted, fields, include, envelope, headers),
+    fields(
+        user_id = %id,
+        include_deleted = deleted.include_deleted,
+        fields = tracing::field::Empty,
+        include_addresses = tracing::field::Empty,
+        not_modified = false,
+    )
+)]
+pub async fn get_user(
+    State(state): State<AppState>,
+    id: UserId,
+    Query(deleted): Query<DeletedFilter>,
+    Query(fields): Query<FieldsParams>,
+    Query(include): Query<IncludeParams>,
+    envelope: Envelope,
+    headers: HeaderMap,
+) -> Result<Response, AppError> {
+    let fields = fields.selection()?;
+    record_selected_fields(fields.as_deref());
+    let include_addresses = include.addresses()?;
+    tracing::Span::current().record("include_addresses", include_addresses);
+
+    let user = state.users.get_user(id, deleted.include_deleted).await?;
+
+    // The ETag only covers the user row, so it is left off when addresses are embedded.
+    if include_addresses {
+        let addresses = addresses::fetch_addresses(&state, id).await?;
+        let _span = tracing::info_span!("result.build").entered();
+        let user = UserView::new(user, fields.as_deref());
+        return Ok(respond(envelope, UserWithAddresses { user, addresses }));
+    }
+
+    let etag = user_etag(&user);
+    let if_none_match = headers
+        .get(header::IF_NONE_MATCH)
+        .and_then(|value| value.to_str().ok());
+    if let Some(if_none_match) = if_none_match
+        && if_none_match_matches(if_none_match, &etag)
+    {
+        tracing::Span::current().record("not_modified", true);
+        return Ok((StatusCode::NOT_MODIFIED, [(header::ETAG, etag)]).into_response());
+    }
+
+    let _span = tracing::info_span!("result.build").entered();
+    let user = UserView::new(user, fields.as_deref());
+    Ok((
+        StatusCode::OK,
+        [(header::ETAG, etag)],
+        respond(envelope, user),
+    )
+        .into_response())
+}
+
+/// `HEAD /user/{id}`: an existence check that skips loading the row, answering 200 or
+/// 404 without a body.
+#[instrument(
+    skip(state, deleted),
+    fields(user_id = %id, include_deleted = deleted.include_deleted, head_request = true)
+)]
+pub async fn head_user(
+    State(state): State<AppState>,
+    id: UserId,
+    Query(deleted): Query<DeletedFilter>,
+) -> Result<Response, AppError> {
+    let exists = state.users.user_exists(id, deleted.include_deleted).await?;
+
+    let status = if exists {
+        StatusCode::OK
+    } else {
+        StatusCode::NOT_FOUND
+    };
+    Ok((status, [(header::CONTENT_LENGTH, HeaderValue::from(0))]).into_response())
+}
+
+/// Admin view of a user that also returns soft-deleted rows.
+#[instrument(skip(state, _admin, envelope), fields(user_id = %id))]
+pub async fn get_user_history(
+    State(state): State<AppState>,
+    _admin: AdminKey,
+    id: UserId,
+    envelope: Envelope,
+) -> Result<Response, AppError> {
+    let user = state.users.get_user(id, true).await?;
+
+    Ok(respond(envelope, user))
+}
+
+/// The `Idempotency-Key` header as a UUID, so any spelling of one key claims the same row.
+fn idempotency_key(headers: &HeaderMap) -> Result<Option<Uuid>, AppError> {
+    let Some(value) = headers.get(IDEMPOTENCY_KEY_HEADER) else {
+        return Ok(None);
+    };
+    value
+        .to_str()
+        .ok()
+        .and_then(|key| Uuid::parse_str(key).ok())
+        .map(Some)
+        .ok_or_else(|| AppError::Validation("Idempotency-Key must be a UUID".to_owned()))
+}
+
+#[instrument(
+    skip(state, envelope, headers, body),
+    fields(
+        user_first_name = %body.first_name,
+        request.content_type = content_type,
+        idempotency_key = tracing::field::Empty,
+        idempotent_replay = false,
+        return_minimal = tracing::field::Empty,
+    )
+)]
 pub async fn add_user(
     State(state): State<AppState>,
-    Json(body): Json<CreateUserRequest>,
-) -> Result<impl IntoResponse, AppError> {
-    let id = Uuid::new_v4();
-
-    sqlx::query("INSERT INTO users (id, first_name, last_name) VALUES ($1, $2, $3)")
-        .bind(id)
-        .bind(&body.first_name)
-        .bind(&body.last_name)
-        .execute(&state.db)
-        .instrument(tracing::info_span!("db.query", db.statement = "INSERT user"))
+    envelope: Envelope,
+    headers: HeaderMap,
+    JsonOrForm(body, content_type): JsonOrForm<CreateUserRequest>,
+) -> Result<Response, AppError> {
+    let return_minimal = prefers(&headers, "return=minimal");
+    tracing::Span::current().record("return_minimal", return_minimal);
+    let idempotency_key = idempotency_key(&headers)?.map(|key| key.to_string());
+    if let Some(key) = &idempotency_key {
+        tracing::Span::current().record("idempotency_key", key.as_str());
+    }
+
+    let created = match state
+        .users
+        .create_user(body, idempotency_key.as_deref())
         .await
-        .context("Failed to insert user")?;
+    {
+        Err(ServiceError::EmailTaken) => return Err(conflict(&state, "email")),
+        result => result?,
+    };
+    if created.replayed {
+        tracing::Span::current().record("idempotent_replay", true);
+    } else {
+        state.users_created_counter.add(1, &[]);
+    }
 
-    state.users_created_counter.add(1, &[]);
+    Ok(created_user(&state, created.user, envelope, return_minimal))
+}
 
-    let user = {
-        let _span = tracing::info_span!("result.build").entered();
-        User {
-            id,
-            first_name: body.first_name,
-            last_name: body.last_name,
-        }
+/// The 201 for a created user. With `Prefer: return=minimal` only `Location` is sent,
+/// and `Preference-Applied` confirms the body was left out.
+fn created_user(
+    state: &AppState,
+    user: User,
+    envelope: Envelope,
+    return_minimal: bool,
+) -> Response {
+    let location = format!("{}/user/{}", state.base_path, user.id);
+    if return_minimal {
+        return (
+            StatusCode::CREATED,
+            [
+                (header::LOCATION, location),
+                (PREFERENCE_APPLIED, "return=minimal".to_owned()),
+            ],
+        )
+            .into_response();
+    }
+    (
+        StatusCode::CREATED,
+        [(header::LOCATION, location)],
+        respond(envelope, user),
+    )
+        .into_response()
+}
+
+#[instrument(skip(state, envelope, body), fields(batch_size = body.len()))]
+pub async fn add_users(
+    State(state): State<AppState>,
+    envelope: Envelope,
+    JsonBody(body): JsonBody<Vec<CreateUserRequest>>,
+) -> Result<Response, AppError> {
+    if body.len() > state.max_bulk_users {
+        return Err(AppError::Validation(format!(
+            "batch must not exceed {} users",
+            state.max_bulk_users
+        )));
+    }
+    let users = match state.users.create_users(&body).await {
+        Err(ServiceError::EmailTaken) => return Err(conflict(&state, "email")),
+        result => result?,
     };
 
-    Ok((StatusCode::CREATED, Json(user)))
+    state.users_created_counter.add(users.len() as u64, &[]);
+
+    Ok((StatusCode::CREATED, respond(envelope, users)).into_response())
+}
+
+#[instrument(skip(state), fields(user_id = %id))]
+pub async fn delete_user(
+    State(state): State<AppState>,
+    id: UserId,
+) -> Result<StatusCode, AppError> {
+    state.users.delete_user(id).await?;
+    state.users_deleted_counter.add(1, &[]);
+
+    Ok(StatusCode::NO_CONTENT)
+}
+
+/// Clears `deleted_at` on a soft-deleted user. Restoring a user that is not
+/// deleted is a no-op that returns 200 with the current user, so retries are safe.
+#[instrument(skip(state, envelope), fields(user_id = %id))]
+pub async fn restore_user(
+    State(state): State<AppState>,
+    id: UserId,
+    envelope: Envelope,
+) -> Result<Response, AppError> {
+    let restored = state.users.restore_user(id).await?;
+    if restored.restored {
+        state.users_restored_counter.add(1, &[]);
+    }
+
+    let _span = tracing::info_span!("result.build").entered();
+    Ok(respond(envelope, restored.user))
+}
+
+/// Folds `remove` into `keep` in one transaction: addresses, audit entries and, when
+/// `keep` has none, the avatar move over, then `remove` is soft-deleted and a `merge`
+/// entry is added to the audit trail of `keep`.
+#[instrument(
+    skip(state, envelope, body),
+    fields(
+        keep_id = %body.keep,
+        remove_id = %body.remove,
+        addresses_moved = tracing::field::Empty,
+        audit_entries_moved = tracing::field::Empty,
+    )
+)]
+pub async fn merge_users(
+    State(state): State<AppState>,
+    envelope: Envelope,
+    JsonBody(body): JsonBody<MergeUsersRequest>,
+) -> Result<Response, AppError> {
+    let MergeUsersRequest { keep, remove } = body;
+    if keep == remove {
+        return Err(AppError::Validation(
+            "keep and remove must be different users".to_owned(),
+        ));
+    }
+
+    let MergedUsers {
+        kept,
+        addresses_moved,
+        audit_entries_moved,
+    } = state.users.merge_users(keep, remove).await?;
+
+    let span = tracing::Span::current();
+    span.record("addresses_moved", addresses_moved);
+    span.record("audit_entries_moved", audit_entries_moved);
+    state.users_merged_counter.add(1, &[]);
+
+    let _span = tracing::info_span!("result.build").entered();
+    Ok(respond(envelope, kept))
+}
+
+fn invalid_if_match() -> AppError {
+    AppError::Validation("If-Match must be * or a quoted user version".to_owned())
+}
+
+/// Version the client expects to overwrite, from `If-Match` or else the body.
+/// `If-Match: *` skips the check and yields `None`.
+fn expected_version(
+    headers: &HeaderMap,
+    body_version: Option<i32>,
+) -> Result<Option<i32>, AppError> {
+    let Some(value) = headers.get(header::IF_MATCH) else {
+        return body_version.map(Some).ok_or(AppError::PreconditionRequired);
+    };
+    let value = value.to_str().map_err(|_| invalid_if_match())?;
+    if value.trim() == "*" {
+        return Ok(None);
+    }
+    opaque_tag(value)
+        .and_then(|tag| tag.parse().ok())
+        .map(Some)
+        .ok_or_else(invalid_if_match)
+}
+
+async fn update_user_fields(
+    state: &AppState,
+    id: UserId,
+    changes: UserChanges<'_>,
+    statement: &'static str,
+    envelope: Envelope,
+) -> Result<Response, AppError> {
+    let result = state.users.update_user(id, &changes, statement).await;
+    if let Err(ServiceError::VersionMismatch { actual, .. }) = &result {
+        tracing::Span::current().record("actual_version", actual);
+    }
+    let user = result?;
+
+    state.users_updated_counter.add(1, &[]);
+
+    let etag = user_etag(&user);
+    Ok((
+        StatusCode::OK,
+        [(header::ETAG, etag)],
+        respond(envelope, user),
+    )
+        .into_response())
+}
+
+#[instrument(
+    skip(state, envelope, headers, body),
+    fields(user_id = %id, expected_version = tracing::field::Empty, actual_version = tracing::field::Empty)
+)]
+pub async fn update_user(
+    State(state): State<AppState>,
+    id: UserId,
+    envelope: Envelope,
+    headers: HeaderMap,
+    JsonBody(body): JsonBody<UpdateUserRequest>,
+) -> Result<Response, AppError> {
+    let expected_version = expected_version(&headers, body.version)?;
+    tracing::Span::current().record("expected_version", expected_version);
+
+    let changes = UserChanges {
+        first_name: Some(&body.first_name),
+        middle_name: Some(body.middle_name.as_ref()),
+        last_name: Some(&body.last_name),
+        expected_version,
+    };
+    update_user_fields(&state, id, changes, "UPDATE user", envelope).await
+}
+
+#[instrument(
+    skip(state, envelope, headers, body),
+    fields(
+        user_id = %id,
+        first_name_modified = body.first_name.is_some(),
+        middle_name_modified = body.middle_name.is_some(),
+        last_name_modified = body.last_name.is_some(),
+        expected_version = tracing::field::Empty,
+        actual_version = tracing::field::Empty,
+    )
+)]
+pub async fn patch_user(
+    State(state): State<AppState>,
+    id: UserId,
+    envelope: Envelope,
+    headers: HeaderMap,
+    JsonBody(body): JsonBody<PatchUserRequest>,
+) -> Result<Response, AppError> {
+    if body.first_name.is_none() && body.middle_name.is_none() && body.last_name.is_none() {
+        return Err(AppError::Validation("No fields to update".to_owned()));
+    }
+    let expected_version = expected_version(&headers, body.version)?;
+    tracing::Span::current().record("expected_version", expected_version);
+
+    let changes = UserChanges {
+        first_name: body.first_name.as_ref(),
+        middle_name: body.middle_name.as_ref().map(Option::as_ref),
+        last_name: body.last_name.as_ref(),
+        expected_version,
+    };
+    update_user_fields(&state, id, changes, "PATCH user", envelope).await
+}
+
+#[instrument(skip(state, ids), fields(requested = ids.len()))]
+pub async fn delete_users(
+    State(state): State<AppState>,
+    JsonBody(ids): JsonBody<Vec<UserId>>,
+) -> Result<impl IntoResponse, AppError> {
+    let deleted = state.users.delete_users(&ids).await?;
+    state.users_deleted_counter.add(deleted, &[]);
+
+    Ok(Json(BulkDeleteResponse {
+        requested: ids.len(),
+        deleted,
+    }))
+}
+
+#[instrument(
+    skip(state, envelope, ids),
+    fields(requested = ids.len(), unique = tracing::field::Empty, found = tracing::field::Empty)
+)]
+pub async fn lookup_users(
+    State(state): State<AppState>,
+    envelope: Envelope,
+    JsonBody(ids): JsonBody<Vec<UserId>>,
+) -> Result<Response, AppError> {
+    if ids.is_empty() {
+        return Err(AppError::Validation("ids must not be empty".to_owned()));
+    }
+    if ids.len() > MAX_LOOKUP_IDS {
+        return Err(AppError::Validation(format!(
+            "at most {MAX_LOOKUP_IDS} ids can be looked up at once"
+        )));
+    }
+    let mut seen = HashSet::with_capacity(ids.len());
+    let ids: Vec<UserId> = ids.into_iter().filter(|id| seen.insert(*id)).collect();
+    tracing::Span::current().record("unique", ids.len());
+
+    let users = state.users.find_users(&ids).await?;
+    tracing::Span::current().record("found", users.len());
+
+    let _span = tracing::info_span!("result.map", row_count = users.len()).entered();
+    let mut found: HashMap<UserId, User> = users.into_iter().map(|user| (user.id, user)).collect();
+    // Answer in request order; ids without a live user are listed as missing.
+    let mut items = Vec::with_capacity(found.len());
+    let mut missing = Vec::new();
+    for id in ids {
+        match found.remove(&id) {
+            Some(user) => items.push(user),
+            None => missing.push(id),
+        }
+    }
+
+    Ok(respond(envelope, LookupResponse { items, missing }))
 }
@@ -0,0 +1,100 @@
+use std::time::Instant;
+
+use axum::{
+    Json,
+    extract::State,
+    http::StatusCode,
+    response::{IntoResponse, Response},
+};
+use tracing::{Instrument, instrument};
+use uuid::Uuid;
+
+use super::record_db_duration_in;
+use crate::error::AppError;
+use crate::models::{Address, CreateAddressRequest, JsonBody, UserId, ValidUuidPair};
+use crate::state::AppState;
+
+/// Addresses of `user_id`, oldest first. Does not check that the user exists.
+pub(super) async fn fetch_addresses(
+    state: &AppState,
+    user_id: UserId,
+) -> Result<Vec<Address>, AppError> {
+    let start = Instant::now();
+    let addresses = state
+        .addresses
+        .find_by_user(user_id)
+        .instrument(tracing::info_span!(
+            "db.query",
+            db.statement = "SELECT addresses BY user_id"
+        ))
+        .await;
+    record_db_duration_in(state, "addresses", "SELECT", start);
+    Ok(addresses?)
+}
+
+#[instrument(skip(state), fields(user_id = %id, address_count = tracing::field::Empty))]
+pub async fn get_addresses(
+    State(state): State<AppState>,
+    id: UserId,
+) -> Result<Response, AppError> {
+    let addresses = fetch_addresses(&state, id).await?;
+    // An empty list is only a 404 when the user itself is missing.
+    if addresses.is_empty() && !state.users.user_exists(id, false).await? {
+        return Err(AppError::user_not_found(id));
+    }
+    tracing::Span::current().record("address_count", addresses.len());
+
+    Ok(Json(addresses).into_response())
+}
+
+#[instrument(skip(state, body), fields(user_id = %id, address_id = tracing::field::Empty))]
+pub async fn add_address(
+    State(state): State<AppState>,
+    id: UserId,
+    JsonBody(body): JsonBody<CreateAddressRequest>,
+) -> Result<Response, AppError> {
+    body.validate().map_err(AppError::InvalidFields)?;
+
+    let address_id = Uuid::new_v4();
+    tracing::Span::current().record("address_id", tracing::field::display(address_id));
+
+    let start = Instant::now();
+    let address = state
+        .addresses
+        .insert(address_id, id, &body)
+        .instrument(tracing::info_span!(
+            "db.query",
+            db.statement = "INSERT address"
+        ))
+        .await;
+    record_db_duration_in(&state, "addresses", "INSERT", start);
+    let address = address?.ok_or(AppError::user_not_found(id))?;
+
+    Ok((StatusCode::CREATED, Json(address)).into_response())
+}
+
+#[instrument(skip(state), fields(user_id = %id, address_id = %address_id))]
+pub async fn delete_address(
+    State(state): State<AppState>,
+    ValidUuidPair(id, address_id): ValidUuidPair,
+) -> Result<StatusCode, AppError> {
+    let start = Instant::now();
+    let deleted = state
+        .addresses
+        .delete(id, address_id)
+        .instrument(tracing::info_span!(
+            "db.query",
+            db.statement = "DELETE address BY id"
+        ))
+        .await;
+    record_db_duration_in(&state, "addresses", "DELETE", start);
+
+    if !deleted? {
+        return Err(AppError::NotFound {
+            resource: "address",
+            id: address_id,
+        });
+    }
+
+    Ok(StatusCode::NO_CONTENT)
+}
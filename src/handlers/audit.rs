@@ -0,0 +1,88 @@
+use std::time::Instant;
+
+use axum::{
+    Json,
+    extract::{Query, State},
+    response::{IntoResponse, Response},
+};
+use tracing::{Instrument, instrument};
+
+use super::{page_limit, record_db_duration_in};
+use crate::auth::AdminKey;
+use crate::error::AppError;
+use crate::models::{PagedResponse, PaginationParams, UserId};
+use crate::state::AppState;
+
+/// Admin view of the changes made to a user, newest first.
+#[instrument(skip(state, _admin, pagination), fields(user_id = %id, result.total_count = tracing::field::Empty))]
+pub async fn get_user_audit(
+    State(state): State<AppState>,
+    _admin: AdminKey,
+    id: UserId,
+    Query(pagination): Query<PaginationParams>,
+) -> Result<Response, AppError> {
+    let limit = page_limit(&state, pagination.limit)?;
+    let offset = pagination.offset.unwrap_or(0);
+    if pagination.cursor.is_some() {
+        return Err(AppError::Validation(
+            "cursor is not supported for the audit trail".to_owned(),
+        ));
+    }
+
+    let start = Instant::now();
+    let page = state
+        .audit
+        .find_by_user(id, limit, offset)
+        .instrument(tracing::info_span!(
+            "db.query",
+            db.statement = "SELECT user_audit BY user_id",
+            limit,
+            offset
+        ))
+        .await;
+    record_db_duration_in(&state, "user_audit", "SELECT", start);
+    let page = page?;
+
+    // Users created before the audit trigger existed have no entries, so an empty
+    // first page only becomes a 404 when the user row is missing too.
+    let total_count = match page.total_count {
+        Some(total_count) => total_count,
+        None => {
+            if !state.users.user_exists(id, true).await? {
+                return Err(AppError::user_not_found(id));
+            }
+            count_audit_entries(&state, id, offset).await?
+        }
+    };
+    tracing::Span::current().record("result.total_count", total_count);
+
+    Ok(Json(PagedResponse {
+        total_count: Some(total_count),
+        limit,
+        offset,
+        items: page.entries,
+    })
+    .into_response())
+}
+
+/// The window count is lost when the page is past the end, so it is counted separately.
+async fn count_audit_entries(
+    state: &AppState,
+    user_id: UserId,
+    offset: u32,
+) -> Result<i64, AppError> {
+    if offset == 0 {
+        return Ok(0);
+    }
+    let start = Instant::now();
+    let count = state
+        .audit
+        .count_by_user(user_id)
+        .instrument(tracing::info_span!(
+            "db.query",
+            db.statement = "COUNT user_audit BY user_id"
+        ))
+        .await;
+    record_db_duration_in(state, "user_audit", "SELECT", start);
+    Ok(count?)
+}
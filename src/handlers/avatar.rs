@@ -0,0 +1,115 @@
+use std::time::Instant;
+
+use anyhow::Context;
+use axum::{
+    extract::State,
+    http::{HeaderMap, HeaderValue, StatusCode, header},
+    response::{IntoResponse, Response},
+};
+use chrono::{DateTime, Utc};
+use tracing::{Instrument, instrument};
+
+use super::{if_none_match_matches, record_db_duration_in};
+use crate::error::AppError;
+use crate::models::{ImageBody, UserId};
+use crate::state::AppState;
+
+pub const MAX_AVATAR_BYTES: usize = 1024 * 1024;
+/// Clients may reuse an avatar this long before revalidating it with its ETag.
+const AVATAR_CACHE_CONTROL: &str = "private, max-age=300";
+
+fn avatar_etag(digest: &str) -> String {
+    format!("\"{digest}\"")
+}
+
+fn http_date(at: DateTime<Utc>) -> String {
+    at.format("%a, %d %b %Y %H:%M:%S GMT").to_string()
+}
+
+#[instrument(
+    skip(state, body),
+    fields(user_id = %id, avatar.size = body.bytes.len(), avatar.content_type = body.content_type)
+)]
+pub async fn put_avatar(
+    State(state): State<AppState>,
+    id: UserId,
+    body: ImageBody,
+) -> Result<Response, AppError> {
+    let start = Instant::now();
+    let digest = state
+        .avatars
+        .upsert(id, body.content_type, &body.bytes)
+        .instrument(tracing::info_span!(
+            "db.query",
+            db.statement = "UPSERT user_avatar"
+        ))
+        .await;
+    record_db_duration_in(&state, "user_avatars", "INSERT", start);
+    let digest = digest?.ok_or(AppError::user_not_found(id))?;
+
+    state.avatar_size.record(body.bytes.len() as u64, &[]);
+    Ok((
+        StatusCode::NO_CONTENT,
+        [(header::ETAG, avatar_etag(&digest))],
+    )
+        .into_response())
+}
+
+#[instrument(
+    skip(state, headers),
+    fields(user_id = %id, avatar.size = tracing::field::Empty, not_modified = false)
+)]
+pub async fn get_avatar(
+    State(state): State<AppState>,
+    id: UserId,
+    headers: HeaderMap,
+) -> Result<Response, AppError> {
+    let start = Instant::now();
+    let avatar = state
+        .avatars
+        .find_by_user(id)
+        .instrument(tracing::info_span!(
+            "db.query",
+            db.statement = "SELECT user_avatar BY user_id"
+        ))
+        .await;
+    record_db_duration_in(&state, "user_avatars", "SELECT", start);
+    let avatar = avatar?.ok_or(AppError::NotFound {
+        resource: "avatar",
+        id: id.as_uuid(),
+    })?;
+
+    let _span = tracing::info_span!("result.build").entered();
+    let etag = avatar_etag(&avatar.digest);
+    let last_modified = http_date(avatar.updated_at);
+    let cache_headers = [
+        (header::ETAG, etag.clone()),
+        (header::LAST_MODIFIED, last_modified),
+        (header::CACHE_CONTROL, AVATAR_CACHE_CONTROL.to_owned()),
+    ];
+    let if_none_match = headers
+        .get(header::IF_NONE_MATCH)
+        .and_then(|value| value.to_str().ok());
+    if let Some(if_none_match) = if_none_match
+        && if_none_match_matches(if_none_match, &etag)
+    {
+        tracing::Span::current().record("not_modified", true);
+        return Ok((StatusCode::NOT_MODIFIED, cache_headers).into_response());
+    }
+
+    tracing::Span::current().record("avatar.size", avatar.data.len());
+    let content_type = HeaderValue::from_str(&avatar.content_type)
+        .context("Stored avatar content type is invalid")?;
+    Ok((
+        [
+            (header::CONTENT_TYPE, content_type),
+            (
+                header::X_CONTENT_TYPE_OPTIONS,
+                HeaderValue::from_static("nosniff"),
+            ),
+        ],
+        cache_headers,
+        avatar.data,
+    )
+        .into_response())
+}
@@ -0,0 +1,58 @@
+use std::time::Instant;
+
+use axum::{
+    Json,
+    extract::State,
+    http::header,
+    response::{IntoResponse, Response},
+};
+use tracing::{Instrument, instrument};
+
+use super::record_db_duration;
+use crate::error::AppError;
+use crate::models::UserStats;
+use crate::state::AppState;
+
+const STATS_DAYS: i32 = 30;
+const STATS_TOP_LAST_NAMES: i64 = 10;
+/// The aggregates scan the whole table, so clients and proxies may reuse them briefly.
+const STATS_CACHE_CONTROL: &str = "max-age=60";
+
+/// Runs each aggregate as its own `db.query` span, since together they dominate latency.
+#[instrument(skip(state), fields(result.total = tracing::field::Empty))]
+pub async fn get_user_stats(State(state): State<AppState>) -> Result<Response, AppError> {
+    let total = state.users.count_users(None, false).await?;
+    tracing::Span::current().record("result.total", total);
+
+    let start = Instant::now();
+    let created_per_day = state
+        .stats
+        .created_per_day(STATS_DAYS)
+        .instrument(tracing::info_span!(
+            "db.query",
+            db.statement = "COUNT users GROUP BY created_at day",
+            stats.days = STATS_DAYS,
+        ))
+        .await;
+    record_db_duration(&state, "SELECT", start);
+    let created_per_day = created_per_day?;
+
+    let start = Instant::now();
+    let top_last_names = state
+        .stats
+        .top_last_names(STATS_TOP_LAST_NAMES)
+        .instrument(tracing::info_span!(
+            "db.query",
+            db.statement = "COUNT users GROUP BY last_name"
+        ))
+        .await;
+    record_db_duration(&state, "SELECT", start);
+    let top_last_names = top_last_names?;
+
+    let stats = UserStats {
+        total,
+        created_per_day,
+        top_last_names,
+    };
+    Ok(([(header::CACHE_CONTROL, STATS_CACHE_CONTROL)], Json(stats)).into_response())
+}
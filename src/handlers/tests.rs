@@ -0,0 +1,895 @@
+use std::sync::{Arc, atomic::AtomicBool};
+
+use axum::{
+    Router,
+    body::{Body, to_bytes},
+    http::{HeaderValue, Request, StatusCode, header},
+    middleware::from_fn_with_state,
+    response::IntoResponse,
+    routing::{delete, get, post},
+};
+use opentelemetry::metrics::MeterProvider;
+use opentelemetry_sdk::metrics::SdkMeterProvider;
+use serde_json::{Value, json};
+use sqlx::postgres::PgPoolOptions;
+use tower::ServiceExt;
+
+use super::{
+    add_user, delete_user, get_user, get_users, head_user, patch_user, update_user, users_exist,
+};
+use crate::error::AppError;
+use crate::middleware::{WriteAuth, advertise_max_page_size, hash_api_key, require_write_auth};
+use crate::models::{User, UserId};
+use crate::otel;
+use crate::pagination::{DEFAULT_MAX_PAGE_SIZE, PageSize};
+use crate::repository::{
+    InMemoryUserRepository, PostgresAddressRepository, PostgresAuditRepository,
+    PostgresAvatarRepository, PostgresHealth, PostgresStatsRepository,
+};
+use crate::service::UserService;
+use crate::state::{AppState, TimeOrderedIds};
+
+fn user(first_name: &str) -> User {
+    User {
+        id: UserId::random(),
+        first_name: first_name.to_owned().try_into().unwrap(),
+        middle_name: None,
+        last_name: "Lovelace".to_owned().try_into().unwrap(),
+        email: format!("{}@example.com", first_name.to_lowercase()),
+        created_at: "2026-01-01T00:00:00Z".parse().unwrap(),
+        updated_at: "2026-01-01T00:00:00Z".parse().unwrap(),
+        deleted_at: None,
+        version: 1,
+    }
+}
+
+/// Routes under test backed by `users`. The pool never connects, so a handler that
+/// reaches past the repository fails instead of needing Postgres.
+fn app(users: InMemoryUserRepository) -> Router {
+    router(state(users))
+}
+
+fn app_with_page_size(users: InMemoryUserRepository, page_size: PageSize) -> Router {
+    router(AppState {
+        page_size,
+        ..state(users)
+    })
+}
+
+/// State with writes left open, as with `AUTH_MODE=none`.
+fn state(users: InMemoryUserRepository) -> AppState {
+    let meter = SdkMeterProvider::builder().build().meter("test");
+    let pool = PgPoolOptions::new()
+        .connect_lazy("postgres://localhost/unused")
+        .expect("lazy pool");
+    AppState {
+        users: UserService::new(
+            Arc::new(users),
+            Arc::new(TimeOrderedIds),
+            Vec::new(),
+            otel::db_operation_duration_histogram(&meter),
+        ),
+        addresses: Arc::new(PostgresAddressRepository(pool.clone())),
+        audit: Arc::new(PostgresAuditRepository(pool.clone())),
+        avatars: Arc::new(PostgresAvatarRepository(pool.clone())),
+        stats: Arc::new(PostgresStatsRepository(pool.clone())),
+        health: Arc::new(PostgresHealth(pool)),
+        ready_flag: Arc::new(AtomicBool::new(true)),
+        max_bulk_users: 10,
+        max_body_bytes: 4096,
+        page_size: PageSize {
+            max: DEFAULT_MAX_PAGE_SIZE,
+            clamp: false,
+        },
+        base_path: Arc::from(""),
+        admin_api_key: None,
+        write_auth: WriteAuth::Disabled,
+        response_envelope: false,
+        users_created_counter: meter.u64_counter("app.users.created").build(),
+        users_updated_counter: meter.u64_counter("app.users.updated").build(),
+        users_deleted_counter: meter.u64_counter("app.users.deleted").build(),
+        users_restored_counter: meter.u64_counter("app.users.restored").build(),
+        users_conflict_counter: meter.u64_counter("app.users.conflict").build(),
+        users_merged_counter: meter.u64_counter("app.users.merged").build(),
+        http_request_duration: otel::http_request_duration_histogram(&meter),
+        http_active_requests: otel::http_active_requests_counter(&meter),
+        db_operation_duration: otel::db_operation_duration_histogram(&meter),
+        health_db_check_duration: otel::health_db_check_duration_histogram(&meter),
+        avatar_size: otel::avatar_size_histogram(&meter),
+        #[cfg(feature = "prometheus")]
+        prometheus: Default::default(),
+    }
+}
+
+fn router(state: AppState) -> Router {
+    Router::new()
+        .route(
+            "/users",
+            get(get_users).route_layer(from_fn_with_state(state.clone(), advertise_max_page_size)),
+        )
+        .route("/users/exists", get(users_exist))
+        .route(
+            "/user/{id}",
+            get(get_user).head(head_user).merge(
+                delete(delete_user)
+                    .put(update_user)
+                    .patch(patch_user)
+                    .route_layer(from_fn_with_state(state.clone(), require_write_auth)),
+            ),
+        )
+        .route("/user", post(add_user))
+        .with_state(state)
+}
+
+async fn send(app: Router, request: Request<Body>) -> (StatusCode, header::HeaderMap, Value) {
+    let response = app.oneshot(request).await.expect("infallible");
+    let status = response.status();
+    let headers = response.headers().clone();
+    let body = to_bytes(response.into_body(), usize::MAX)
+        .await
+        .expect("body");
+    let body = if body.is_empty() {
+        Value::Null
+    } else {
+        serde_json::from_slice(&body).expect("JSON body")
+    };
+    (status, headers, body)
+}
+
+fn get_request(uri: &str) -> Request<Body> {
+    Request::get(uri).body(Body::empty()).unwrap()
+}
+
+fn post_json(uri: &str, body: Value) -> Request<Body> {
+    json_request("POST", uri, body)
+}
+
+fn json_request(method: &str, uri: &str, body: Value) -> Request<Body> {
+    Request::builder()
+        .method(method)
+        .uri(uri)
+        .header(header::CONTENT_TYPE, "application/json")
+        .body(Body::from(body.to_string()))
+        .unwrap()
+}
+
+fn with_middle_name(first_name: &str, middle_name: &str) -> User {
+    User {
+        middle_name: Some(middle_name.to_owned().try_into().unwrap()),
+        ..user(first_name)
+    }
+}
+
+#[tokio::test]
+async fn get_user_returns_the_user_with_its_etag() {
+    let ada = user("Ada");
+    let app = app(InMemoryUserRepository::with_users([ada.clone()]));
+
+    let (status, headers, body) = send(app, get_request(&format!("/user/{}", ada.id))).await;
+
+    assert_eq!(status, StatusCode::OK);
+    assert_eq!(headers[header::ETAG], "W/\"1\"");
+    assert_eq!(body["id"], ada.id.to_string());
+    assert_eq!(body["first_name"], "Ada");
+}
+
+#[tokio::test]
+async fn get_user_answers_a_matching_if_none_match_with_304() {
+    let ada = user("Ada");
+    let app = app(InMemoryUserRepository::with_users([ada.clone()]));
+    let request = Request::get(format!("/user/{}", ada.id))
+        .header(header::IF_NONE_MATCH, "W/\"1\"")
+        .body(Body::empty())
+        .unwrap();
+
+    let (status, _, body) = send(app, request).await;
+
+    assert_eq!(status, StatusCode::NOT_MODIFIED);
+    assert_eq!(body, Value::Null);
+}
+
+#[tokio::test]
+async fn get_user_answers_a_stale_if_none_match_with_the_user() {
+    let ada = user("Ada");
+    let app = app(InMemoryUserRepository::with_users([ada.clone()]));
+    let request = Request::get(format!("/user/{}", ada.id))
+        .header(header::IF_NONE_MATCH, "W/\"0\", \"7\"")
+        .body(Body::empty())
+        .unwrap();
+
+    let (status, headers, body) = send(app, request).await;
+
+    assert_eq!(status, StatusCode::OK);
+    assert_eq!(headers[header::ETAG], "W/\"1\"");
+    assert_eq!(body["first_name"], "Ada");
+}
+
+#[tokio::test]
+async fn get_user_ignores_a_malformed_if_none_match() {
+    let ada = user("Ada");
+    let app = app(InMemoryUserRepository::with_users([ada.clone()]));
+
+    let values: [&[u8]; 6] = [b"1", b"W/1", b"\"1", b"W/\"", b",", b"\"1\xff\""];
+    for value in values {
+        let request = Request::get(format!("/user/{}", ada.id))
+            .header(
+                header::IF_NONE_MATCH,
+                HeaderValue::from_bytes(value).unwrap(),
+            )
+            .body(Body::empty())
+            .unwrap();
+
+        let (status, _, body) = send(app.clone(), request).await;
+
+        assert_eq!(status, StatusCode::OK, "{value:?}");
+        assert_eq!(body["first_name"], "Ada", "{value:?}");
+    }
+}
+
+#[tokio::test]
+async fn get_user_answers_an_unknown_id_with_a_json_404() {
+    let id = UserId::random();
+    let app = app(InMemoryUserRepository::with_users([]));
+
+    let (status, headers, body) = send(app, get_request(&format!("/user/{id}"))).await;
+
+    assert_eq!(status, StatusCode::NOT_FOUND);
+    assert_eq!(headers[header::CONTENT_TYPE], "application/json");
+    assert_eq!(body["error"]["code"], "not_found");
+    assert_eq!(body["error"]["resource"], "user");
+    assert_eq!(body["error"]["id"], json!(id.to_string()));
+}
+
+#[tokio::test]
+async fn get_user_hides_soft_deleted_users_unless_asked() {
+    let mut ada = user("Ada");
+    ada.deleted_at = Some("2026-01-02T00:00:00Z".parse().unwrap());
+    let app = app(InMemoryUserRepository::with_users([ada.clone()]));
+
+    let (status, _, body) = send(app.clone(), get_request(&format!("/user/{}", ada.id))).await;
+    assert_eq!(status, StatusCode::NOT_FOUND);
+    assert_eq!(body["error"]["code"], "not_found");
+    assert_eq!(body["error"]["id"], ada.id.to_string());
+
+    let (status, _, body) = send(
+        app,
+        get_request(&format!("/user/{}?include_deleted=true", ada.id)),
+    )
+    .await;
+    assert_eq!(status, StatusCode::OK);
+    assert_eq!(body["deleted_at"], "2026-01-02T00:00:00Z");
+}
+
+#[tokio::test]
+async fn get_user_rejects_a_malformed_id() {
+    let app = app(InMemoryUserRepository::default());
+
+    let (status, _, body) = send(app, get_request("/user/not-a-uuid")).await;
+
+    assert_eq!(status, StatusCode::BAD_REQUEST);
+    assert_eq!(body["error"]["code"], "invalid_id");
+}
+
+#[tokio::test]
+async fn get_user_applies_sparse_fields() {
+    let ada = user("Ada");
+    let app = app(InMemoryUserRepository::with_users([ada.clone()]));
+
+    let (status, _, body) = send(
+        app,
+        get_request(&format!("/user/{}?fields=id,email", ada.id)),
+    )
+    .await;
+
+    assert_eq!(status, StatusCode::OK);
+    assert_eq!(body, json!({ "id": ada.id, "email": "ada@example.com" }));
+}
+
+#[tokio::test]
+async fn head_user_reports_existence_without_a_body() {
+    let ada = user("Ada");
+    let app = app(InMemoryUserRepository::with_users([ada.clone()]));
+    let head = |id: UserId| {
+        Request::head(format!("/user/{id}"))
+            .body(Body::empty())
+            .unwrap()
+    };
+
+    let (status, headers, body) = send(app.clone(), head(ada.id)).await;
+    assert_eq!(status, StatusCode::OK);
+    assert_eq!(headers[header::CONTENT_LENGTH], "0");
+    assert_eq!(body, Value::Null);
+
+    let (status, headers, body) = send(app, head(UserId::random())).await;
+    assert_eq!(status, StatusCode::NOT_FOUND);
+    assert_eq!(headers[header::CONTENT_LENGTH], "0");
+    assert_eq!(body, Value::Null);
+}
+
+#[tokio::test]
+async fn delete_user_answers_a_second_delete_with_404() {
+    let ada = user("Ada");
+    let app = app(InMemoryUserRepository::with_users([ada.clone()]));
+    let delete = || {
+        Request::delete(format!("/user/{}", ada.id))
+            .body(Body::empty())
+            .unwrap()
+    };
+
+    let (status, _, body) = send(app.clone(), delete()).await;
+    assert_eq!(status, StatusCode::NO_CONTENT);
+    assert_eq!(body, Value::Null);
+
+    let (status, _, body) = send(app, delete()).await;
+    assert_eq!(status, StatusCode::NOT_FOUND);
+    assert_eq!(body["error"]["id"], ada.id.to_string());
+}
+
+#[tokio::test]
+async fn delete_user_requires_the_api_key() {
+    let ada = user("Ada");
+    let app = router(AppState {
+        write_auth: WriteAuth::ApiKey(hash_api_key("s3cret")),
+        ..state(InMemoryUserRepository::with_users([ada.clone()]))
+    });
+    let delete = |key: Option<&str>| {
+        let mut request = Request::delete(format!("/user/{}", ada.id));
+        if let Some(key) = key {
+            request = request.header("x-api-key", key);
+        }
+        request.body(Body::empty()).unwrap()
+    };
+
+    for key in [None, Some("wrong")] {
+        let (status, _, body) = send(app.clone(), delete(key)).await;
+        assert_eq!(status, StatusCode::UNAUTHORIZED, "{key:?}");
+        assert_eq!(body["error"]["code"], "unauthorized", "{key:?}");
+    }
+
+    let (status, _, _) = send(app, delete(Some("s3cret"))).await;
+    assert_eq!(status, StatusCode::NO_CONTENT);
+}
+
+#[tokio::test]
+async fn update_user_replaces_every_field_and_clears_a_left_out_middle_name() {
+    let ada = with_middle_name("Ada", "Augusta");
+    let app = app(InMemoryUserRepository::with_users([ada.clone()]));
+    let request = json!({ "first_name": "Augusta", "last_name": "King", "version": 1 });
+
+    let (status, _, body) = send(
+        app,
+        json_request("PUT", &format!("/user/{}", ada.id), request),
+    )
+    .await;
+
+    assert_eq!(status, StatusCode::OK);
+    assert_eq!(body["first_name"], "Augusta");
+    assert_eq!(body["middle_name"], Value::Null);
+    assert_eq!(body["last_name"], "King");
+}
+
+#[tokio::test]
+async fn update_user_requires_every_name() {
+    let ada = user("Ada");
+    let app = app(InMemoryUserRepository::with_users([ada.clone()]));
+    let request = json!({ "first_name": "Augusta", "version": 1 });
+
+    let (status, _, body) = send(
+        app,
+        json_request("PUT", &format!("/user/{}", ada.id), request),
+    )
+    .await;
+
+    assert_eq!(status, StatusCode::BAD_REQUEST);
+    assert_eq!(body["error"]["errors"][0]["field"], "last_name");
+}
+
+#[tokio::test]
+async fn patch_user_keeps_an_absent_middle_name_and_clears_a_null_one() {
+    let cases = [
+        (
+            json!({ "last_name": "King", "version": 1 }),
+            json!("Augusta"),
+        ),
+        (
+            json!({ "middle_name": "Byron", "version": 1 }),
+            json!("Byron"),
+        ),
+        (json!({ "middle_name": null, "version": 1 }), Value::Null),
+    ];
+    for (request, middle_name) in cases {
+        let ada = with_middle_name("Ada", "Augusta");
+        let app = app(InMemoryUserRepository::with_users([ada.clone()]));
+
+        let (status, _, body) = send(
+            app,
+            json_request("PATCH", &format!("/user/{}", ada.id), request.clone()),
+        )
+        .await;
+
+        assert_eq!(status, StatusCode::OK, "{request}");
+        assert_eq!(body["middle_name"], middle_name, "{request}");
+    }
+}
+
+#[tokio::test]
+async fn add_user_creates_a_user_that_can_be_fetched() {
+    let app = app(InMemoryUserRepository::default());
+    let request = post_json(
+        "/user",
+        json!({ "first_name": "Grace", "last_name": "Hopper", "email": "grace@example.com" }),
+    );
+
+    let (status, headers, created) = send(app.clone(), request).await;
+    assert_eq!(status, StatusCode::CREATED);
+    assert_eq!(created["version"], 1);
+    let id = created["id"].as_str().expect("id");
+    assert_eq!(headers[header::LOCATION], format!("/user/{id}"));
+
+    let (status, _, fetched) = send(app, get_request(&format!("/user/{id}"))).await;
+    assert_eq!(status, StatusCode::OK);
+    assert_eq!(fetched, created);
+}
+
+fn create_grace(prefer: Option<&str>) -> Request<Body> {
+    let mut request = post_json(
+        "/user",
+        json!({ "first_name": "Grace", "last_name": "Hopper", "email": "grace@example.com" }),
+    );
+    if let Some(prefer) = prefer {
+        request
+            .headers_mut()
+            .insert("prefer", prefer.parse().unwrap());
+    }
+    request
+}
+
+#[tokio::test]
+async fn add_user_with_return_minimal_sends_only_the_location() {
+    let app = app(InMemoryUserRepository::default());
+
+    let (status, headers, body) = send(app.clone(), create_grace(Some("return=minimal"))).await;
+    assert_eq!(status, StatusCode::CREATED);
+    assert_eq!(body, Value::Null);
+    assert_eq!(headers["preference-applied"], "return=minimal");
+
+    let location = headers[header::LOCATION].to_str().unwrap();
+    let (status, _, fetched) = send(app, get_request(location)).await;
+    assert_eq!(status, StatusCode::OK);
+    assert_eq!(fetched["first_name"], "Grace");
+}
+
+#[tokio::test]
+async fn add_user_finds_return_minimal_among_other_preferences() {
+    let app = app(InMemoryUserRepository::default());
+
+    let (status, headers, body) =
+        send(app, create_grace(Some("respond-async, RETURN=minimal"))).await;
+
+    assert_eq!(status, StatusCode::CREATED);
+    assert_eq!(body, Value::Null);
+    assert_eq!(headers["preference-applied"], "return=minimal");
+}
+
+#[tokio::test]
+async fn add_user_ignores_unrecognized_preferences() {
+    for prefer in [
+        None,
+        Some("return=representation"),
+        Some("return=banana; x=y, wait"),
+    ] {
+        let app = app(InMemoryUserRepository::default());
+
+        let (status, headers, body) = send(app, create_grace(prefer)).await;
+
+        assert_eq!(status, StatusCode::CREATED, "Prefer: {prefer:?}");
+        assert_eq!(body["first_name"], "Grace", "Prefer: {prefer:?}");
+        assert!(headers.contains_key(header::LOCATION), "Prefer: {prefer:?}");
+        assert!(
+            !headers.contains_key("preference-applied"),
+            "Prefer: {prefer:?}"
+        );
+    }
+}
+
+fn create_grace_with_key(key: &str) -> Request<Body> {
+    let mut request = create_grace(None);
+    request
+        .headers_mut()
+        .insert("idempotency-key", key.parse().unwrap());
+    request
+}
+
+#[tokio::test]
+async fn add_user_replays_a_repeated_idempotency_key() {
+    let app = app(InMemoryUserRepository::default());
+    let key = "0190b6f4-6a1e-7c3a-9a57-1c2d3e4f5a6b";
+
+    let (status, _, created) = send(app.clone(), create_grace_with_key(key)).await;
+    assert_eq!(status, StatusCode::CREATED);
+
+    let (status, _, replayed) = send(app, create_grace_with_key(&key.to_uppercase())).await;
+    assert_eq!(status, StatusCode::CREATED);
+    assert_eq!(replayed, created);
+}
+
+#[tokio::test]
+async fn add_user_rejects_an_idempotency_key_that_is_not_a_uuid() {
+    let app = app(InMemoryUserRepository::default());
+
+    let (status, _, body) = send(app, create_grace_with_key("retry-1")).await;
+
+    assert_eq!(status, StatusCode::BAD_REQUEST);
+    assert_eq!(body["error"]["code"], "validation_error");
+    assert_eq!(body["error"]["message"], "Idempotency-Key must be a UUID");
+}
+
+#[tokio::test]
+async fn add_user_rejects_invalid_fields() {
+    let app = app(InMemoryUserRepository::default());
+    let request = post_json(
+        "/user",
+        json!({ "first_name": " ", "last_name": "Hopper", "email": "grace@example.com" }),
+    );
+
+    let (status, _, body) = send(app, request).await;
+
+    assert_eq!(status, StatusCode::UNPROCESSABLE_ENTITY);
+    assert_eq!(body["error"]["code"], "validation_error");
+    assert_eq!(
+        body["error"]["errors"],
+        json!([{ "field": "first_name", "message": "must not be blank" }])
+    );
+}
+
+#[tokio::test]
+async fn add_user_rejects_an_invalid_name_in_a_form() {
+    let app = app(InMemoryUserRepository::default());
+    let request = Request::post("/user")
+        .header(header::CONTENT_TYPE, "application/x-www-form-urlencoded")
+        .body(Body::from(format!(
+            "first_name=Grace&last_name={}&email=grace%40example.com",
+            "x".repeat(101)
+        )))
+        .unwrap();
+
+    let (status, _, body) = send(app, request).await;
+
+    assert_eq!(status, StatusCode::UNPROCESSABLE_ENTITY);
+    assert_eq!(
+        body["error"]["errors"],
+        json!([{ "field": "last_name", "message": "must be at most 100 characters" }])
+    );
+}
+
+#[tokio::test]
+async fn add_user_trims_names() {
+    let app = app(InMemoryUserRepository::default());
+    let request = post_json(
+        "/user",
+        json!({ "first_name": " Grace ", "last_name": "Hopper\t", "email": "grace@example.com" }),
+    );
+
+    let (status, _, body) = send(app, request).await;
+
+    assert_eq!(status, StatusCode::CREATED);
+    assert_eq!(body["first_name"], "Grace");
+    assert_eq!(body["last_name"], "Hopper");
+}
+
+#[tokio::test]
+async fn add_user_tells_absent_null_and_empty_middle_names_apart() {
+    let cases = [
+        (
+            json!({ "first_name": "Grace", "last_name": "Hopper", "email": "a@example.com" }),
+            Some(Value::Null),
+        ),
+        (
+            json!({ "first_name": "Grace", "middle_name": null, "last_name": "Hopper", "email": "b@example.com" }),
+            Some(Value::Null),
+        ),
+        (
+            json!({ "first_name": "Grace", "middle_name": "Brewster", "last_name": "Hopper", "email": "c@example.com" }),
+            Some(json!("Brewster")),
+        ),
+        (
+            json!({ "first_name": "Grace", "middle_name": "", "last_name": "Hopper", "email": "d@example.com" }),
+            None,
+        ),
+    ];
+    for (request, stored) in cases {
+        let app = app(InMemoryUserRepository::default());
+
+        let (status, _, body) = send(app, post_json("/user", request.clone())).await;
+
+        match stored {
+            Some(middle_name) => {
+                assert_eq!(status, StatusCode::CREATED, "{request}");
+                assert_eq!(body["middle_name"], middle_name, "{request}");
+            }
+            None => {
+                assert_eq!(status, StatusCode::UNPROCESSABLE_ENTITY, "{request}");
+                assert_eq!(body["error"]["errors"][0]["field"], "middle_name");
+            }
+        }
+    }
+}
+
+#[tokio::test]
+async fn add_user_trims_the_middle_name() {
+    let app = app(InMemoryUserRepository::default());
+    let request = json!({ "first_name": "Grace", "middle_name": "  Brewster ", "last_name": "Hopper", "email": "grace@example.com" });
+
+    let (status, _, body) = send(app, post_json("/user", request)).await;
+
+    assert_eq!(status, StatusCode::CREATED);
+    assert_eq!(body["middle_name"], "Brewster");
+}
+
+#[tokio::test]
+async fn add_user_rejects_a_malformed_email() {
+    for email in [
+        "grace",
+        "grace@",
+        "@example.com",
+        "grace@example",
+        "grace @example.com",
+        "a@b@example.com",
+    ] {
+        let app = app(InMemoryUserRepository::default());
+        let request = json!({ "first_name": "Grace", "last_name": "Hopper", "email": email });
+
+        let (status, _, body) = send(app, post_json("/user", request)).await;
+
+        assert_eq!(status, StatusCode::UNPROCESSABLE_ENTITY, "{email}");
+        assert_eq!(body["error"]["errors"][0]["field"], "email", "{email}");
+    }
+}
+
+#[tokio::test]
+async fn add_user_rejects_unsupported_content_types() {
+    let app = app(InMemoryUserRepository::default());
+    let request = Request::post("/user")
+        .header(header::CONTENT_TYPE, "text/plain")
+        .body(Body::from("Grace"))
+        .unwrap();
+
+    let (status, _, body) = send(app, request).await;
+
+    assert_eq!(status, StatusCode::UNSUPPORTED_MEDIA_TYPE);
+    assert_eq!(body["error"]["code"], "unsupported_media_type");
+}
+
+#[tokio::test]
+async fn add_user_rejects_an_unknown_field_and_lists_the_accepted_ones() {
+    let app = app(InMemoryUserRepository::default());
+    let body = json!({
+        "firstname": "Grace",
+        "last_name": "Hopper",
+        "email": "grace@example.com",
+    });
+
+    let (status, _, body) = send(app, post_json("/user", body)).await;
+
+    assert_eq!(status, StatusCode::BAD_REQUEST);
+    assert_eq!(body["error"]["code"], "invalid_body");
+    let error = &body["error"]["errors"][0];
+    assert_eq!(error["field"], "firstname");
+    let message = error["message"].as_str().unwrap();
+    assert!(
+        message.starts_with("unknown field `firstname`"),
+        "{message}"
+    );
+    assert!(message.contains("`first_name`"), "{message}");
+}
+
+#[tokio::test]
+async fn add_user_rejects_a_missing_field() {
+    let app = app(InMemoryUserRepository::default());
+    let body = json!({ "first_name": "Grace", "last_name": "Hopper" });
+
+    let (status, _, body) = send(app, post_json("/user", body)).await;
+
+    assert_eq!(status, StatusCode::BAD_REQUEST);
+    assert_eq!(body["error"]["code"], "invalid_body");
+    assert_eq!(body["error"]["errors"][0]["field"], "email");
+    assert_eq!(
+        body["error"]["errors"][0]["message"],
+        "missing field `email`"
+    );
+}
+
+#[tokio::test]
+async fn add_user_rejects_a_field_of_the_wrong_type() {
+    let app = app(InMemoryUserRepository::default());
+    let body = json!({ "first_name": "Grace", "last_name": "Hopper", "email": 42 });
+
+    let (status, _, body) = send(app, post_json("/user", body)).await;
+
+    assert_eq!(status, StatusCode::BAD_REQUEST);
+    assert_eq!(body["error"]["code"], "invalid_body");
+    assert_eq!(body["error"]["errors"][0]["field"], "email");
+    let message = body["error"]["errors"][0]["message"].as_str().unwrap();
+    assert!(
+        message.starts_with("invalid type: integer `42`"),
+        "{message}"
+    );
+}
+
+#[tokio::test]
+async fn add_user_rejects_a_body_that_is_not_utf8() {
+    let app = app(InMemoryUserRepository::default());
+    let mut bytes = br#"{"first_name": "Gr"#.to_vec();
+    bytes.push(0xff);
+    bytes.extend_from_slice(br#"ce", "last_name": "Hopper", "email": "grace@example.com"}"#);
+    let request = Request::post("/user")
+        .header(header::CONTENT_TYPE, "application/json")
+        .body(Body::from(bytes))
+        .unwrap();
+
+    let (status, _, body) = send(app, request).await;
+
+    assert_eq!(status, StatusCode::BAD_REQUEST);
+    assert_eq!(body["error"]["code"], "invalid_body");
+    let message = body["error"]["message"].as_str().unwrap();
+    assert!(
+        message.starts_with("request body is not valid JSON"),
+        "{message}"
+    );
+}
+
+#[tokio::test]
+async fn get_users_rejects_a_limit_over_the_maximum() {
+    let page_size = PageSize {
+        max: 2,
+        clamp: false,
+    };
+    let app = app_with_page_size(InMemoryUserRepository::with_users([user("Ada")]), page_size);
+
+    let (status, headers, body) = send(app, get_request("/users?limit=3")).await;
+
+    assert_eq!(status, StatusCode::BAD_REQUEST);
+    assert_eq!(headers["x-max-page-size"], "2");
+    assert_eq!(body["error"]["message"], "limit must not exceed 2");
+}
+
+#[tokio::test]
+async fn get_users_rejects_a_zero_limit() {
+    let page_size = PageSize {
+        max: 2,
+        clamp: true,
+    };
+    let app = app_with_page_size(InMemoryUserRepository::with_users([user("Ada")]), page_size);
+
+    let (status, _, body) = send(app, get_request("/users?limit=0")).await;
+
+    assert_eq!(status, StatusCode::BAD_REQUEST);
+    assert_eq!(body["error"]["code"], "validation_error");
+    assert_eq!(body["error"]["message"], "limit must be at least 1");
+}
+
+#[tokio::test]
+async fn get_users_clamps_a_limit_over_the_maximum_when_configured() {
+    let page_size = PageSize {
+        max: 2,
+        clamp: true,
+    };
+    let users = InMemoryUserRepository::with_users([user("Ada"), user("Grace"), user("Alan")]);
+    let app = app_with_page_size(users, page_size);
+
+    let (status, headers, body) = send(app, get_request("/users?limit=1000")).await;
+
+    assert_eq!(status, StatusCode::OK);
+    assert_eq!(headers["x-max-page-size"], "2");
+    assert_eq!(body["limit"], 2);
+    assert_eq!(body["items"].as_array().map(Vec::len), Some(2));
+    assert_eq!(body["total_count"], 3);
+}
+
+#[tokio::test]
+async fn get_users_defaults_to_no_more_than_the_maximum() {
+    let page_size = PageSize {
+        max: 1,
+        clamp: false,
+    };
+    let app = app_with_page_size(
+        InMemoryUserRepository::with_users([user("Ada"), user("Grace")]),
+        page_size,
+    );
+
+    let (status, _, body) = send(app, get_request("/users")).await;
+
+    assert_eq!(status, StatusCode::OK);
+    assert_eq!(body["limit"], 1);
+}
+
+#[tokio::test]
+async fn users_exist_matches_names_ignoring_case() {
+    let ada = user("Ada");
+    let mut deleted = user("Ada");
+    deleted.deleted_at = Some("2026-01-02T00:00:00Z".parse().unwrap());
+    let app = app(InMemoryUserRepository::with_users([
+        ada.clone(),
+        deleted,
+        user("Grace"),
+    ]));
+
+    let (status, _, body) = send(
+        app,
+        get_request("/users/exists?first_name=ADA&last_name=lovelace"),
+    )
+    .await;
+
+    assert_eq!(status, StatusCode::OK);
+    assert_eq!(body, json!({ "exists": true, "count": 1, "ids": [ada.id] }));
+}
+
+#[tokio::test]
+async fn users_exist_caps_the_ids() {
+    let app = app(InMemoryUserRepository::with_users(
+        (0..12).map(|_| user("Ann")),
+    ));
+
+    let (status, _, body) = send(
+        app,
+        get_request("/users/exists?first_name=Ann&last_name=Lovelace"),
+    )
+    .await;
+
+    assert_eq!(status, StatusCode::OK);
+    assert_eq!(body["count"], 12);
+    assert_eq!(body["ids"].as_array().map(Vec::len), Some(10));
+}
+
+#[tokio::test]
+async fn users_exist_requires_both_names() {
+    for uri in [
+        "/users/exists?first_name=Ann",
+        "/users/exists?first_name=Ann&last_name=%20",
+    ] {
+        let app = app(InMemoryUserRepository::default());
+
+        let (status, _, body) = send(app, get_request(uri)).await;
+
+        assert_eq!(status, StatusCode::BAD_REQUEST, "{uri}");
+        assert_eq!(body["error"]["message"], "last_name is required", "{uri}");
+    }
+}
+
+#[tokio::test]
+async fn user_ids_are_sent_as_plain_uuid_strings() {
+    let ada = user("Ada");
+    let app = app(InMemoryUserRepository::with_users([ada.clone()]));
+
+    let (_, _, body) = send(app, get_request(&format!("/user/{}", ada.id))).await;
+
+    assert_eq!(body["id"], json!(ada.id.as_uuid().to_string()));
+    assert_eq!(
+        serde_json::from_value::<UserId>(body["id"].clone()).ok(),
+        Some(ada.id)
+    );
+}
+
+#[tokio::test]
+async fn a_user_row_that_does_not_decode_is_an_internal_error() {
+    // What `find_all` returns when a column's type has drifted from `User`.
+    let mismatched = sqlx::Error::ColumnDecode {
+        index: "\"first_name\"".to_owned(),
+        source: "mismatched types; Rust type `String` is not compatible with SQL type `INT4`"
+            .into(),
+    };
+    let err = AppError::from(anyhow::Error::new(mismatched).context("Failed to read users"));
+
+    let response = err.into_response();
+    let status = response.status();
+    let body: Value = serde_json::from_slice(
+        &to_bytes(response.into_body(), usize::MAX)
+            .await
+            .expect("body"),
+    )
+    .expect("JSON body");
+
+    assert_eq!(status, StatusCode::INTERNAL_SERVER_ERROR);
+    assert_eq!(body["error"]["code"], "internal_error");
+    assert_eq!(body["error"]["message"], "internal server error");
+}
@@ -0,0 +1,57 @@
+use std::sync::atomic::Ordering;
+use std::time::{Duration, Instant};
+
+use axum::{Json, extract::State, http::StatusCode, response::IntoResponse};
+use serde_json::json;
+
+use crate::state::AppState;
+
+const DB_CHECK_TIMEOUT: Duration = Duration::from_secs(2);
+
+pub async fn health_check(State(state): State<AppState>) -> impl IntoResponse {
+    let start = Instant::now();
+    let result = tokio::time::timeout(DB_CHECK_TIMEOUT, state.health.ping()).await;
+    state
+        .health_db_check_duration
+        .record(start.elapsed().as_secs_f64(), &[]);
+
+    let error = match result {
+        Ok(Ok(())) => return (StatusCode::OK, Json(json!({"status": "ok", "db": "up"}))),
+        Ok(Err(err)) => err.to_string(),
+        Err(_) => format!(
+            "database check timed out after {}s",
+            DB_CHECK_TIMEOUT.as_secs()
+        ),
+    };
+
+    (
+        StatusCode::SERVICE_UNAVAILABLE,
+        Json(json!({"status": "degraded", "db": "down", "error": error})),
+    )
+}
+
+pub async fn livez() -> impl IntoResponse {
+    (StatusCode::OK, Json(json!({"alive": true})))
+}
+
+pub async fn readyz(State(state): State<AppState>) -> impl IntoResponse {
+    if !state.ready_flag.load(Ordering::Acquire) {
+        return not_ready("migrations have not completed");
+    }
+
+    let result = tokio::time::timeout(DB_CHECK_TIMEOUT, state.health.migrations_succeeded()).await;
+
+    match result {
+        Ok(Ok(true)) => (StatusCode::OK, Json(json!({"ready": true}))),
+        Ok(Ok(false)) => not_ready("a migration is marked as failed"),
+        Ok(Err(err)) => not_ready(&err.to_string()),
+        Err(_) => not_ready("database check timed out"),
+    }
+}
+
+fn not_ready(reason: &str) -> (StatusCode, Json<serde_json::Value>) {
+    (
+        StatusCode::SERVICE_UNAVAILABLE,
+        Json(json!({"ready": false, "reason": reason})),
+    )
+}
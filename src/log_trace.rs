@@ -0,0 +1,74 @@
+#[cfg(test)]
+mod tests;
+
+use std::fmt;
+
+use serde_json::{Map, Value};
+use tracing::{Event, Subscriber};
+use tracing_opentelemetry::OtelData;
+use tracing_subscriber::{
+    fmt::{FmtContext, FormatEvent, FormatFields, format::Writer},
+    registry::LookupSpan,
+};
+
+/// Adds the current trace and span ids to every log line: as a `trace_id=.. span_id=..`
+/// prefix on text lines, and as top-level `trace_id` and `span_id` fields on JSON lines.
+pub struct TraceIdFormat<F> {
+    inner: F,
+    json: bool,
+}
+
+impl<F> TraceIdFormat<F> {
+    /// Wraps a text formatter.
+    pub fn new(inner: F) -> Self {
+        Self { inner, json: false }
+    }
+
+    /// Wraps a formatter that writes one JSON object per line.
+    pub fn json(inner: F) -> Self {
+        Self { inner, json: true }
+    }
+}
+
+impl<S, N, F> FormatEvent<S, N> for TraceIdFormat<F>
+where
+    S: Subscriber + for<'a> LookupSpan<'a>,
+    N: for<'a> FormatFields<'a> + 'static,
+    F: FormatEvent<S, N>,
+{
+    fn format_event(
+        &self,
+        ctx: &FmtContext<'_, S, N>,
+        mut writer: Writer<'_>,
+        event: &Event<'_>,
+    ) -> fmt::Result {
+        // `Span::current()` is unavailable while the subscriber is dispatching, so the
+        // OpenTelemetry context is read from the span's extensions instead.
+        let ids = ctx.lookup_current().and_then(|span| {
+            let extensions = span.extensions();
+            let otel_data = extensions.get::<OtelData>()?;
+            Some((otel_data.trace_id()?, otel_data.span_id()?))
+        });
+        let Some((trace_id, span_id)) = ids else {
+            return self.inner.format_event(ctx, writer, event);
+        };
+
+        if !self.json {
+            write!(writer, "trace_id={trace_id} span_id={span_id} ")?;
+            return self.inner.format_event(ctx, writer, event);
+        }
+
+        let mut line = String::new();
+        self.inner
+            .format_event(ctx, Writer::new(&mut line), event)?;
+        match serde_json::from_str::<Map<String, Value>>(&line) {
+            Ok(mut fields) => {
+                fields.insert("trace_id".to_owned(), trace_id.to_string().into());
+                fields.insert("span_id".to_owned(), span_id.to_string().into());
+                writeln!(writer, "{}", Value::Object(fields))
+            }
+            // Not an object after all; keep the line rather than lose the event.
+            Err(_) => writer.write_str(&line),
+        }
+    }
+}
@@ -0,0 +1,78 @@
+use std::io;
+use std::sync::{Arc, Mutex};
+
+use opentelemetry::trace::{TraceContextExt, TracerProvider};
+use opentelemetry_sdk::trace::SdkTracerProvider;
+use serde_json::Value;
+use tracing_opentelemetry::OpenTelemetrySpanExt;
+use tracing_subscriber::{fmt::MakeWriter, layer::SubscriberExt};
+
+use super::TraceIdFormat;
+
+/// Collects everything the fmt layer writes.
+#[derive(Clone, Default)]
+struct Output(Arc<Mutex<Vec<u8>>>);
+
+impl io::Write for Output {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        self.0.lock().unwrap().write(buf)
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        Ok(())
+    }
+}
+
+impl<'a> MakeWriter<'a> for Output {
+    type Writer = Output;
+
+    fn make_writer(&'a self) -> Self::Writer {
+        self.clone()
+    }
+}
+
+/// Logs one event inside a traced span and returns the JSON line and the span's ids.
+fn log_json_in_span() -> (Value, String, String) {
+    let output = Output::default();
+    let provider = SdkTracerProvider::builder().build();
+    let subscriber = tracing_subscriber::registry()
+        .with(
+            tracing_subscriber::fmt::layer()
+                .json()
+                .with_writer(output.clone())
+                .event_format(TraceIdFormat::json(
+                    tracing_subscriber::fmt::format().json(),
+                )),
+        )
+        .with(tracing_opentelemetry::layer().with_tracer(provider.tracer("test")));
+
+    let (trace_id, span_id) = tracing::subscriber::with_default(subscriber, || {
+        let span = tracing::info_span!("request");
+        let _entered = span.enter();
+        tracing::info!(user_id = 7, "user fetched");
+        let context = span.context();
+        let span_context = context.span().span_context().clone();
+        (
+            span_context.trace_id().to_string(),
+            span_context.span_id().to_string(),
+        )
+    });
+
+    let bytes = output.0.lock().unwrap().clone();
+    let line = String::from_utf8(bytes).unwrap();
+    (
+        serde_json::from_str(line.trim_end()).expect("one JSON line"),
+        trace_id,
+        span_id,
+    )
+}
+
+#[test]
+fn json_lines_carry_trace_and_span_ids_as_fields() {
+    let (line, trace_id, span_id) = log_json_in_span();
+
+    assert_eq!(line["trace_id"], trace_id);
+    assert_eq!(line["span_id"], span_id);
+    assert_eq!(line["fields"]["message"], "user fetched");
+    assert_eq!(line["fields"]["user_id"], 7);
+}
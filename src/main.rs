@@ -1,18 +1,51 @@
+mod auth;
 mod db;
+mod error;
 mod handlers;
+mod health;
+mod log_trace;
+mod middleware;
 mod models;
 mod otel;
+mod pagination;
+#[cfg(feature = "prometheus")]
+mod prometheus;
+mod repository;
 mod routes;
+mod service;
 mod state;
 
 use anyhow::Context;
 use opentelemetry::metrics::MeterProvider;
 use opentelemetry::trace::TracerProvider;
+use opentelemetry_appender_tracing::layer::OpenTelemetryTracingBridge;
 use std::env;
+use std::str::FromStr;
+use std::sync::{
+    Arc,
+    atomic::{AtomicBool, AtomicU64, Ordering},
+};
+use std::time::Duration;
 use tokio::net::TcpListener;
-use tracing_subscriber::{EnvFilter, fmt::format::FmtSpan, layer::SubscriberExt, util::SubscriberInitExt};
+use tracing_subscriber::{
+    EnvFilter, Layer, fmt::format::FmtSpan, layer::SubscriberExt, util::SubscriberInitExt,
+};
 
-use crate::state::AppState;
+use crate::log_trace::TraceIdFormat;
+use crate::pagination::{DEFAULT_MAX_PAGE_SIZE, PageSize};
+use crate::repository::{
+    PostgresAddressRepository, PostgresAuditRepository, PostgresAvatarRepository, PostgresHealth,
+    PostgresStatsRepository, PostgresUserRepository,
+};
+use crate::service::UserService;
+use crate::state::{AppState, TimeOrderedIds};
+
+const DEFAULT_MAX_BULK_USERS: usize = 500;
+// Bulk inserts bind five parameters per user and Postgres allows 65535 per statement.
+const MAX_BULK_USERS_LIMIT: usize = u16::MAX as usize / 5;
+const DEFAULT_REQUEST_TIMEOUT_MS: u64 = 30_000;
+const DEFAULT_MAX_BODY_BYTES: usize = 4 * 1024;
+const DEFAULT_USERS_TOTAL_REFRESH_MS: u64 = 30_000;
 
 #[tokio::main]
 async fn main() -> anyhow::Result<()> {
@@ -20,43 +53,211 @@ async fn main() -> anyhow::Result<()> {
 
     let tracer = providers.tracer.tracer("rust-telemetry");
     let otel_layer = tracing_opentelemetry::layer().with_tracer(tracer);
-    let fmt_layer = tracing_subscriber::fmt::layer()
-	                .with_span_events(FmtSpan::NEW | FmtSpan::CLOSE);
+    let log_layer = OpenTelemetryTracingBridge::new(&providers.log_provider)
+        .with_filter(otel::log_bridge_filter());
+    let json_logs = match env::var("LOG_FORMAT").as_deref() {
+        Ok("json") => true,
+        Ok("text") | Err(_) => false,
+        Ok(other) => anyhow::bail!("LOG_FORMAT must be text or json, got {other:?}"),
+    };
+    let text_layer = (!json_logs).then(|| {
+        tracing_subscriber::fmt::layer()
+            .with_span_events(FmtSpan::NEW | FmtSpan::CLOSE)
+            .event_format(TraceIdFormat::new(tracing_subscriber::fmt::format()))
+    });
+    let json_layer = json_logs.then(|| {
+        tracing_subscriber::fmt::layer()
+            .json()
+            .with_span_events(FmtSpan::NEW | FmtSpan::CLOSE)
+            .event_format(TraceIdFormat::json(
+                tracing_subscriber::fmt::format().json(),
+            ))
+    });
     tracing_subscriber::registry()
         .with(EnvFilter::from_default_env())
-        .with(fmt_layer)
+        .with(text_layer)
+        .with(json_layer)
         .with(otel_layer)
+        .with(log_layer)
         .init();
 
+    // The subscriber only exists from here on, so the exporter choice is logged now.
+    match &providers.otlp_endpoint {
+        Some(endpoint) => tracing::info!(%endpoint, "Exporting telemetry over OTLP"),
+        None => {
+            tracing::info!("OTEL_EXPORTER_OTLP_ENDPOINT is not set, printing telemetry to stdout")
+        }
+    }
+    tracing::info!(sampler = %providers.sampler, "Trace sampler configured");
+    tracing::info!(propagators = %providers.propagators, "Trace context propagators configured");
+    if let Some(batch) = &providers.span_batch {
+        tracing::info!(
+            max_queue_size = batch.max_queue_size,
+            max_export_batch_size = batch.max_export_batch_size,
+            export_timeout_ms = batch.export_timeout.as_millis() as u64,
+            "Batch span processor configured"
+        );
+    }
+    tracing::info!(
+        interval_ms = providers.metric_export.interval.as_millis() as u64,
+        timeout_ms = providers.metric_export.timeout.as_millis() as u64,
+        temporality = ?providers.metric_export.temporality,
+        "Periodic metric export configured"
+    );
+    for warning in &providers.warnings {
+        tracing::warn!("{warning}");
+    }
+
     let database_url = env::var("DATABASE_URL").context("DATABASE_URL must be set")?;
-    let pool = db::create_pool(&database_url).await?;
+    let max_bulk_users = env_positive("MAX_BULK_USERS")?.unwrap_or(DEFAULT_MAX_BULK_USERS);
+    anyhow::ensure!(
+        max_bulk_users <= MAX_BULK_USERS_LIMIT,
+        "MAX_BULK_USERS must not exceed {MAX_BULK_USERS_LIMIT}"
+    );
+    let max_body_bytes = env_positive("MAX_BODY_BYTES")?.unwrap_or(DEFAULT_MAX_BODY_BYTES);
+    let max_page_size = env_positive("MAX_PAGE_SIZE")?.unwrap_or(DEFAULT_MAX_PAGE_SIZE);
+    let clamp_page_size = match env::var("PAGE_SIZE_OVERFLOW").as_deref() {
+        Ok("clamp") => true,
+        Ok("reject") | Err(_) => false,
+        Ok(other) => anyhow::bail!("PAGE_SIZE_OVERFLOW must be reject or clamp, got {other:?}"),
+    };
+    let response_envelope = env_parse("RESPONSE_ENVELOPE", "true or false")?.unwrap_or(false);
+    let users_total_refresh =
+        env_positive("USERS_TOTAL_REFRESH_MS")?.unwrap_or(DEFAULT_USERS_TOTAL_REFRESH_MS);
+    let admin_api_key = env::var("ADMIN_API_KEY")
+        .ok()
+        .filter(|key| !key.is_empty())
+        .map(Arc::from);
+    let write_auth = match env::var("AUTH_MODE").as_deref() {
+        Ok("jwt") => {
+            let secret =
+                env::var("JWT_SECRET").context("JWT_SECRET must be set when AUTH_MODE=jwt")?;
+            anyhow::ensure!(!secret.is_empty(), "JWT_SECRET must not be empty");
+            middleware::WriteAuth::jwt(&secret)
+        }
+        Ok("api_key") | Err(_) => {
+            let api_key = env::var("API_KEY")
+                .ok()
+                .filter(|key| !key.is_empty())
+                .context("API_KEY must be set, or AUTH_MODE=none to leave writes open")?;
+            middleware::WriteAuth::ApiKey(middleware::hash_api_key(&api_key))
+        }
+        Ok("none") => {
+            tracing::warn!("AUTH_MODE=none, write endpoints are unauthenticated");
+            middleware::WriteAuth::Disabled
+        }
+        Ok(other) => anyhow::bail!("AUTH_MODE must be api_key, jwt or none, got {other:?}"),
+    };
+    let max_connections =
+        env_positive("DB_MAX_CONNECTIONS")?.unwrap_or(db::DEFAULT_MAX_CONNECTIONS);
+    let min_connections = env_parse("DB_MIN_CONNECTIONS", "a non-negative integer")?
+        .unwrap_or(db::DEFAULT_MIN_CONNECTIONS.min(max_connections));
+    anyhow::ensure!(
+        min_connections <= max_connections,
+        "DB_MIN_CONNECTIONS must not exceed DB_MAX_CONNECTIONS"
+    );
+    let pool_options = db::PoolOptions {
+        max_connections,
+        min_connections,
+        connect_timeout: env_positive("DB_CONNECT_TIMEOUT_MS")?.map(Duration::from_millis),
+        idle_timeout: env_positive("DB_IDLE_TIMEOUT_MS")?.map(Duration::from_millis),
+    };
+    let pool = db::create_pool_with_options(&database_url, pool_options).await?;
+    let ready_flag = Arc::new(AtomicBool::new(false));
 
     sqlx::migrate!("./migrations")
         .run(&pool)
         .await
         .context("Failed to run migrations")?;
+    ready_flag.store(true, Ordering::Release);
+    db::spawn_idempotency_key_cleanup(pool.clone());
+    let users_total = Arc::new(AtomicU64::new(0));
+    db::spawn_user_count_refresh(
+        pool.clone(),
+        Duration::from_millis(users_total_refresh),
+        users_total.clone(),
+    );
 
     tracing::info!("Connected to database and migrations applied");
 
     let meter = providers.meter.meter("rust-telemetry");
 
     let users_created_counter = meter.u64_counter("app.users.created").build();
+    let users_updated_counter = meter.u64_counter("app.users.updated").build();
+    let users_deleted_counter = meter.u64_counter("app.users.deleted").build();
+    let users_restored_counter = meter.u64_counter("app.users.restored").build();
+    let users_conflict_counter = meter.u64_counter("app.users.conflict").build();
+    let users_merged_counter = meter.u64_counter("app.users.merged").build();
+    let http_request_duration = otel::http_request_duration_histogram(&meter);
+    let http_active_requests = otel::http_active_requests_counter(&meter);
+    let db_operation_duration = otel::db_operation_duration_histogram(&meter);
+    let health_db_check_duration = otel::health_db_check_duration_histogram(&meter);
+    let avatar_size = otel::avatar_size_histogram(&meter);
 
-    let gauge_pool = pool.clone();
-    let _pool_gauge = meter
-        .u64_observable_gauge("db.client.connections.pool_size")
+    let _pool_gauges = db::register_pool_gauges(&meter, &pool);
+
+    let _users_total_gauge = meter
+        .u64_observable_gauge("app.users.total")
+        .with_unit("{user}")
+        .with_description("Number of users that are not soft-deleted")
         .with_callback(move |observer| {
-            observer.observe(gauge_pool.size() as u64, &[]);
+            observer.observe(users_total.load(Ordering::Relaxed), &[]);
         })
         .build();
 
     let state = AppState {
-        db: pool,
+        users: UserService::new(
+            Arc::new(PostgresUserRepository(pool.clone())),
+            Arc::new(TimeOrderedIds),
+            Vec::new(),
+            db_operation_duration.clone(),
+        ),
+        addresses: Arc::new(PostgresAddressRepository(pool.clone())),
+        audit: Arc::new(PostgresAuditRepository(pool.clone())),
+        avatars: Arc::new(PostgresAvatarRepository(pool.clone())),
+        stats: Arc::new(PostgresStatsRepository(pool.clone())),
+        health: Arc::new(PostgresHealth(pool)),
+        ready_flag,
+        max_bulk_users,
+        max_body_bytes,
+        page_size: PageSize {
+            max: max_page_size,
+            clamp: clamp_page_size,
+        },
+        base_path: Arc::from(routes::BASE_PATH),
+        admin_api_key,
+        write_auth,
+        response_envelope,
         users_created_counter,
+        users_updated_counter,
+        users_deleted_counter,
+        users_restored_counter,
+        users_conflict_counter,
+        users_merged_counter,
+        http_request_duration,
+        http_active_requests,
+        db_operation_duration,
+        health_db_check_duration,
+        avatar_size,
+        #[cfg(feature = "prometheus")]
+        prometheus: providers.prometheus.clone(),
     };
 
-    let app = routes::create_router(state);
-    let listener = TcpListener::bind("0.0.0.0:3000").await.context("Failed to bind")?;
+    let cors = routes::CorsConfig::parse(
+        &env::var("CORS_ALLOWED_ORIGINS").unwrap_or_else(|_| "*".to_owned()),
+    )?;
+    let compression_enabled =
+        env_parse("RESPONSE_COMPRESSION_ENABLED", "true or false")?.unwrap_or(true);
+    let request_timeout = env_positive("REQUEST_TIMEOUT_MS")?.unwrap_or(DEFAULT_REQUEST_TIMEOUT_MS);
+    let app = routes::create_router(
+        state,
+        cors,
+        compression_enabled,
+        Duration::from_millis(request_timeout),
+    );
+    let listener = TcpListener::bind("0.0.0.0:3000")
+        .await
+        .context("Failed to bind")?;
     tracing::info!("Listening on 0.0.0.0:3000");
 
     axum::serve(listener, app)
@@ -66,6 +267,7 @@ async fn main() -> anyhow::Result<()> {
 
     let _ = providers.tracer.shutdown();
     let _ = providers.meter.shutdown();
+    let _ = providers.log_provider.shutdown();
 
     Ok(())
 }
@@ -76,3 +278,25 @@ async fn shutdown_signal() {
         .expect("Failed to install CTRL+C signal handler");
     tracing::info!("Shutdown signal received, flushing telemetry...");
 }
+
+/// Parses the environment variable `name`, or returns `None` when it is unset.
+/// `expected` describes a valid value for the error message.
+fn env_parse<T: FromStr>(name: &str, expected: &str) -> anyhow::Result<Option<T>> {
+    match env::var(name) {
+        Ok(value) => value
+            .parse()
+            .map(Some)
+            .map_err(|_| anyhow::anyhow!("{name} must be {expected}, got {value:?}")),
+        Err(_) => Ok(None),
+    }
+}
+
+/// Like [`env_parse`] for counts, sizes and durations, where zero is also rejected.
+fn env_positive<T: FromStr + Default + PartialEq>(name: &str) -> anyhow::Result<Option<T>> {
+    let value = env_parse(name, "a positive integer")?;
+    anyhow::ensure!(
+        value != Some(T::default()),
+        "{name} must be a positive integer, got \"0\""
+    );
+    Ok(value)
+}
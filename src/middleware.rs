@@ -0,0 +1,108 @@
+mod auth;
+mod baggage;
+mod request_id;
+
+use std::time::Instant;
+
+use axum::{
+    BoxError,
+    extract::{MatchedPath, Request, State},
+    http::{HeaderName, HeaderValue},
+    middleware::Next,
+    response::Response,
+};
+use opentelemetry::{KeyValue, metrics::UpDownCounter};
+use tower::timeout::error::Elapsed;
+
+use crate::error::AppError;
+use crate::state::AppState;
+
+pub use auth::{WriteAuth, hash_api_key, require_write_auth};
+pub use baggage::{BaggageLayer, TenantIdProcessor};
+pub use request_id::RequestIdLayer;
+
+const X_MAX_PAGE_SIZE: HeaderName = HeaderName::from_static("x-max-page-size");
+
+pub async fn record_request_duration(
+    State(state): State<AppState>,
+    request: Request,
+    next: Next,
+) -> Response {
+    let start = Instant::now();
+    let method = request.method().to_string();
+    let route = request
+        .extensions()
+        .get::<MatchedPath>()
+        .map(|path| path.as_str().to_owned());
+
+    let response = next.run(request).await;
+
+    let mut attributes = vec![
+        KeyValue::new("http.request.method", method),
+        KeyValue::new(
+            "http.response.status_code",
+            i64::from(response.status().as_u16()),
+        ),
+    ];
+    if let Some(route) = route {
+        attributes.push(KeyValue::new("http.route", route));
+    }
+    state
+        .http_request_duration
+        .record(start.elapsed().as_secs_f64(), &attributes);
+
+    response
+}
+
+struct ActiveRequestGuard {
+    counter: UpDownCounter<i64>,
+    attributes: [KeyValue; 1],
+}
+
+impl Drop for ActiveRequestGuard {
+    fn drop(&mut self) {
+        self.counter.add(-1, &self.attributes);
+    }
+}
+
+pub async fn track_active_requests(
+    State(state): State<AppState>,
+    request: Request,
+    next: Next,
+) -> Response {
+    let attributes = [KeyValue::new(
+        "http.request.method",
+        request.method().to_string(),
+    )];
+    state.http_active_requests.add(1, &attributes);
+    // Decrement on drop so cancelled requests are not left counted as in-flight.
+    let _guard = ActiveRequestGuard {
+        counter: state.http_active_requests.clone(),
+        attributes,
+    };
+
+    next.run(request).await
+}
+
+/// Sends the largest accepted `limit` with every listing response, errors included,
+/// so a client can correct an oversized page request.
+pub async fn advertise_max_page_size(
+    State(state): State<AppState>,
+    request: Request,
+    next: Next,
+) -> Response {
+    let mut response = next.run(request).await;
+    response
+        .headers_mut()
+        .insert(X_MAX_PAGE_SIZE, HeaderValue::from(state.page_size.max));
+    response
+}
+
+/// Turns errors from the timeout layer into the JSON error envelope.
+pub async fn handle_timeout_error(err: BoxError) -> AppError {
+    if err.is::<Elapsed>() {
+        AppError::Timeout
+    } else {
+        AppError::DbError(anyhow::anyhow!(err))
+    }
+}
@@ -0,0 +1,106 @@
+use axum::{
+    extract::{Request, State},
+    http::header,
+    middleware::Next,
+    response::Response,
+};
+use jsonwebtoken::{Algorithm, DecodingKey, Validation};
+use serde::Deserialize;
+use sha2::{Digest, Sha256};
+use tracing_opentelemetry::OpenTelemetrySpanExt;
+
+use crate::auth::constant_time_eq;
+use crate::error::AppError;
+use crate::state::AppState;
+
+const API_KEY_HEADER: &str = "x-api-key";
+/// Roles a bearer token must carry to use the write routes.
+const WRITE_ROLES: [&str; 2] = ["admin", "writer"];
+
+/// How write routes authenticate callers, chosen by `AUTH_MODE`.
+#[derive(Clone)]
+pub enum WriteAuth {
+    /// `x-api-key` compared against the SHA-256 of `API_KEY`.
+    ApiKey([u8; 32]),
+    /// `Authorization: Bearer` HS256 tokens signed with `JWT_SECRET`.
+    Jwt(DecodingKey),
+    /// Writes are open. Only chosen by an explicit `AUTH_MODE=none`.
+    Disabled,
+}
+
+impl WriteAuth {
+    pub fn jwt(secret: &str) -> Self {
+        Self::Jwt(DecodingKey::from_secret(secret.as_bytes()))
+    }
+}
+
+/// Claims read from a verified bearer token, available to handlers as a request extension.
+#[derive(Clone, Deserialize)]
+pub struct AuthClaims {
+    pub sub: String,
+    pub role: String,
+}
+
+pub fn hash_api_key(key: &str) -> [u8; 32] {
+    Sha256::digest(key.as_bytes()).into()
+}
+
+/// Guards write routes according to [`WriteAuth`] and records `auth.result` on the span.
+pub async fn require_write_auth(
+    State(state): State<AppState>,
+    mut request: Request,
+    next: Next,
+) -> Result<Response, AppError> {
+    let span = tracing::Span::current();
+    let result = match &state.write_auth {
+        WriteAuth::Disabled => {
+            span.set_attribute("auth.result", "disabled");
+            return Ok(next.run(request).await);
+        }
+        WriteAuth::ApiKey(expected) => check_api_key(&request, expected),
+        WriteAuth::Jwt(key) => check_bearer_token(&request, key).map(|claims| {
+            request.extensions_mut().insert(claims);
+        }),
+    };
+    span.set_attribute("auth.result", if result.is_ok() { "ok" } else { "fail" });
+    result?;
+    Ok(next.run(request).await)
+}
+
+/// The SHA-256 of the `x-api-key` header must equal the hash of `API_KEY`. Comparing
+/// digests keeps the check constant-time regardless of the provided key's length.
+fn check_api_key(request: &Request, expected: &[u8; 32]) -> Result<(), AppError> {
+    let authorized = request
+        .headers()
+        .get(API_KEY_HEADER)
+        .and_then(|value| value.to_str().ok())
+        .is_some_and(|provided| constant_time_eq(&hash_api_key(provided), expected));
+    if authorized {
+        Ok(())
+    } else {
+        Err(AppError::Unauthorized(API_KEY_HEADER))
+    }
+}
+
+fn check_bearer_token(request: &Request, key: &DecodingKey) -> Result<AuthClaims, AppError> {
+    let unauthorized = || AppError::Unauthorized("bearer token");
+    let token = request
+        .headers()
+        .get(header::AUTHORIZATION)
+        .and_then(|value| value.to_str().ok())
+        .and_then(|value| value.strip_prefix("Bearer "))
+        .ok_or_else(unauthorized)?;
+    let claims = jsonwebtoken::decode::<AuthClaims>(token, key, &Validation::new(Algorithm::HS256))
+        .map_err(|err| {
+            tracing::info!(error = %err, "bearer token rejected");
+            unauthorized()
+        })?
+        .claims;
+    let span = tracing::Span::current();
+    span.set_attribute("auth.subject", claims.sub.clone());
+    span.set_attribute("auth.role", claims.role.clone());
+    if !WRITE_ROLES.contains(&claims.role.as_str()) {
+        return Err(AppError::Forbidden(claims.role));
+    }
+    Ok(claims)
+}
@@ -0,0 +1,94 @@
+use std::task::{Context, Poll};
+use std::time::Duration;
+
+use axum::http::{HeaderMap, Request, Response};
+use opentelemetry::baggage::BaggageExt;
+use opentelemetry::context::{FutureExt, WithContext};
+use opentelemetry::propagation::{Extractor, TextMapPropagator};
+use opentelemetry::trace::Span as _;
+use opentelemetry::{Context as OtelContext, KeyValue};
+use opentelemetry_sdk::error::OTelSdkResult;
+use opentelemetry_sdk::propagation::BaggagePropagator;
+use opentelemetry_sdk::trace::{Span, SpanData, SpanProcessor};
+use tower::{Layer, Service};
+use tracing_opentelemetry::OpenTelemetrySpanExt;
+
+const TENANT_ID_KEY: &str = "x-tenant-id";
+
+/// Parses the W3C `baggage` header into the OpenTelemetry context the rest of the request
+/// runs in, where handlers read it with `opentelemetry::Context::current().baggage()`.
+/// An `x-tenant-id` entry is recorded on the current span as `tenant.id`, so this must sit
+/// inside `OtelAxumLayer`. Spans started later in the request get it from
+/// [`TenantIdProcessor`].
+#[derive(Clone, Copy, Default)]
+pub struct BaggageLayer;
+
+impl<S> Layer<S> for BaggageLayer {
+    type Service = Baggage<S>;
+
+    fn layer(&self, inner: S) -> Self::Service {
+        Baggage { inner }
+    }
+}
+
+#[derive(Clone)]
+pub struct Baggage<S> {
+    inner: S,
+}
+
+impl<S, ReqBody, ResBody> Service<Request<ReqBody>> for Baggage<S>
+where
+    S: Service<Request<ReqBody>, Response = Response<ResBody>>,
+{
+    type Response = S::Response;
+    type Error = S::Error;
+    type Future = WithContext<S::Future>;
+
+    fn poll_ready(&mut self, cx: &mut Context<'_>) -> Poll<Result<(), Self::Error>> {
+        self.inner.poll_ready(cx)
+    }
+
+    fn call(&mut self, request: Request<ReqBody>) -> Self::Future {
+        let otel_cx = BaggagePropagator::new().extract(&HeaderExtractor(request.headers()));
+        if let Some(tenant_id) = otel_cx.baggage().get(TENANT_ID_KEY) {
+            tracing::Span::current().set_attribute("tenant.id", tenant_id.as_str().to_owned());
+        }
+        // The context is attached on every poll, so it follows the request across awaits.
+        self.inner.call(request).with_context(otel_cx)
+    }
+}
+
+/// Copies the `x-tenant-id` baggage entry onto every span started in a context that
+/// carries it as `tenant.id`: the handler spans and the `db.query` spans under them.
+#[derive(Debug)]
+pub struct TenantIdProcessor;
+
+impl SpanProcessor for TenantIdProcessor {
+    fn on_start(&self, span: &mut Span, cx: &OtelContext) {
+        if let Some(tenant_id) = cx.baggage().get(TENANT_ID_KEY) {
+            span.set_attribute(KeyValue::new("tenant.id", tenant_id.as_str().to_owned()));
+        }
+    }
+
+    fn on_end(&self, _span: SpanData) {}
+
+    fn force_flush(&self) -> OTelSdkResult {
+        Ok(())
+    }
+
+    fn shutdown_with_timeout(&self, _timeout: Duration) -> OTelSdkResult {
+        Ok(())
+    }
+}
+
+struct HeaderExtractor<'a>(&'a HeaderMap);
+
+impl Extractor for HeaderExtractor<'_> {
+    fn get(&self, key: &str) -> Option<&str> {
+        self.0.get(key).and_then(|value| value.to_str().ok())
+    }
+
+    fn keys(&self) -> Vec<&str> {
+        self.0.keys().map(|name| name.as_str()).collect()
+    }
+}
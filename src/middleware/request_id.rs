@@ -0,0 +1,77 @@
+use std::task::{Context, Poll};
+
+use axum::http::{HeaderName, HeaderValue, Request, Response};
+use futures_util::future::BoxFuture;
+use tower::{Layer, Service};
+use tracing_opentelemetry::OpenTelemetrySpanExt;
+use uuid::Uuid;
+
+static X_REQUEST_ID: HeaderName = HeaderName::from_static("x-request-id");
+const MAX_REQUEST_ID_LEN: usize = 128;
+
+/// Stamps every request with an `x-request-id`, reusing a well-formed incoming one,
+/// records it on the current span as `request.id` and echoes it on the response.
+/// Must sit inside `OtelAxumLayer` so the HTTP server span is current.
+#[derive(Clone, Copy, Default)]
+pub struct RequestIdLayer;
+
+impl<S> Layer<S> for RequestIdLayer {
+    type Service = RequestId<S>;
+
+    fn layer(&self, inner: S) -> Self::Service {
+        RequestId { inner }
+    }
+}
+
+#[derive(Clone)]
+pub struct RequestId<S> {
+    inner: S,
+}
+
+impl<S, ReqBody, ResBody> Service<Request<ReqBody>> for RequestId<S>
+where
+    S: Service<Request<ReqBody>, Response = Response<ResBody>>,
+    S::Future: Send + 'static,
+{
+    type Response = S::Response;
+    type Error = S::Error;
+    type Future = BoxFuture<'static, Result<Self::Response, Self::Error>>;
+
+    fn poll_ready(&mut self, cx: &mut Context<'_>) -> Poll<Result<(), Self::Error>> {
+        self.inner.poll_ready(cx)
+    }
+
+    fn call(&mut self, mut request: Request<ReqBody>) -> Self::Future {
+        let request_id = request
+            .headers()
+            .get(&X_REQUEST_ID)
+            .filter(|value| is_valid_request_id(value))
+            .cloned()
+            .unwrap_or_else(|| {
+                HeaderValue::try_from(Uuid::new_v4().to_string())
+                    .expect("a UUID is a valid header value")
+            });
+        if let Ok(id) = request_id.to_str() {
+            tracing::Span::current().set_attribute("request.id", id.to_owned());
+        }
+        // Handlers see the same id, whether it was supplied or generated.
+        request
+            .headers_mut()
+            .insert(X_REQUEST_ID.clone(), request_id.clone());
+
+        let future = self.inner.call(request);
+        Box::pin(async move {
+            let mut response = future.await?;
+            response
+                .headers_mut()
+                .insert(X_REQUEST_ID.clone(), request_id);
+            Ok(response)
+        })
+    }
+}
+
+fn is_valid_request_id(value: &HeaderValue) -> bool {
+    !value.is_empty()
+        && value.len() <= MAX_REQUEST_ID_LEN
+        && value.as_bytes().iter().all(|byte| byte.is_ascii_graphic())
+}
@@ -1,15 +1,868 @@
-use serde::{Deserialize, Serialize};
+use std::fmt;
+use std::str::FromStr;
+
+use axum::{
+    body::Bytes,
+    extract::{FromRequest, FromRequestParts, Path, Request},
+    http::{HeaderMap, StatusCode, header, request::Parts},
+};
+use chrono::{DateTime, Utc};
+use serde::{Deserialize, Deserializer, Serialize, de::DeserializeOwned};
 use uuid::Uuid;
 
-#[derive(Serialize, Deserialize)]
+use crate::error::AppError;
+use crate::state::AppState;
+
+/// The id of a user, kept apart from other UUIDs such as address ids so one cannot be
+/// passed where the other is expected. It is sent and stored as a plain UUID.
+#[derive(
+    Clone, Copy, Debug, PartialEq, Eq, Hash, PartialOrd, Ord, Serialize, Deserialize, sqlx::Type,
+)]
+#[serde(transparent)]
+#[sqlx(transparent)]
+pub struct UserId(Uuid);
+
+impl UserId {
+    /// A random v4 id, like the ones users created before ids were time-ordered have.
+    #[cfg(test)]
+    pub fn random() -> Self {
+        Self(Uuid::new_v4())
+    }
+
+    pub fn as_uuid(self) -> Uuid {
+        self.0
+    }
+}
+
+impl From<Uuid> for UserId {
+    fn from(id: Uuid) -> Self {
+        Self(id)
+    }
+}
+
+impl fmt::Display for UserId {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        self.0.fmt(f)
+    }
+}
+
+impl FromStr for UserId {
+    type Err = uuid::Error;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        s.parse().map(Self)
+    }
+}
+
+/// A `{id}` path parameter; a malformed UUID is rejected with a JSON 400.
+impl<S: Send + Sync> FromRequestParts<S> for UserId {
+    type Rejection = AppError;
+
+    async fn from_request_parts(parts: &mut Parts, state: &S) -> Result<Self, Self::Rejection> {
+        let Path(raw) = Path::<String>::from_request_parts(parts, state)
+            .await
+            .map_err(|rejection| AppError::Validation(rejection.body_text()))?;
+        parse_path_param(raw)
+    }
+}
+
+/// Why a name, or a free-text field checked like one, was rejected.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum NameError {
+    Blank,
+    TooLong,
+    ControlCharacter,
+}
+
+impl NameError {
+    const ALL: [Self; 3] = [Self::Blank, Self::TooLong, Self::ControlCharacter];
+
+    fn check(value: &str) -> Result<(), Self> {
+        if value.trim().is_empty() {
+            Err(Self::Blank)
+        } else if value.chars().count() > MAX_NAME_LEN {
+            Err(Self::TooLong)
+        } else if value.chars().any(char::is_control) {
+            Err(Self::ControlCharacter)
+        } else {
+            Ok(())
+        }
+    }
+
+    /// Whether a deserialization error message came from a [`NameError`].
+    fn is_name_error(message: &str) -> bool {
+        Self::ALL.iter().any(|err| err.to_string() == message)
+    }
+}
+
+impl fmt::Display for NameError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::Blank => f.write_str("must not be blank"),
+            Self::TooLong => write!(f, "must be at most {MAX_NAME_LEN} characters"),
+            Self::ControlCharacter => f.write_str("must not contain control characters"),
+        }
+    }
+}
+
+/// A name of at most `MAX_NAME_LEN` characters without control characters, with
+/// surrounding whitespace trimmed and nothing left blank. Deserializing checks the same
+/// rules, so an invalid name is rejected before a handler runs. Rows read back from the
+/// database are trusted as they are.
+macro_rules! name_type {
+    ($(#[$attr:meta])* $name:ident) => {
+        $(#[$attr])*
+        #[derive(Clone, Debug, PartialEq, Eq, PartialOrd, Ord, Serialize, Deserialize, sqlx::Type)]
+        #[serde(try_from = "String")]
+        #[sqlx(transparent)]
+        pub struct $name(String);
+
+        impl TryFrom<String> for $name {
+            type Error = NameError;
+
+            fn try_from(value: String) -> Result<Self, Self::Error> {
+                let trimmed = value.trim();
+                NameError::check(trimmed)?;
+                Ok(Self(if trimmed.len() == value.len() { value } else { trimmed.to_owned() }))
+            }
+        }
+
+        impl std::ops::Deref for $name {
+            type Target = str;
+
+            fn deref(&self) -> &str {
+                &self.0
+            }
+        }
+
+        impl PartialEq<&str> for $name {
+            fn eq(&self, other: &&str) -> bool {
+                self.0 == *other
+            }
+        }
+
+        impl fmt::Display for $name {
+            fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+                f.write_str(&self.0)
+            }
+        }
+
+        impl garde::rules::length::chars::HasChars for $name {
+            fn num_chars(&self) -> usize {
+                self.0.chars().count()
+            }
+        }
+    };
+}
+
+name_type!(FirstName);
+name_type!(LastName);
+name_type!(MiddleName);
+
+#[derive(Clone, Serialize, Deserialize, sqlx::FromRow)]
 pub struct User {
+    pub id: UserId,
+    pub first_name: FirstName,
+    /// `null` for users without one; never an empty string.
+    pub middle_name: Option<MiddleName>,
+    pub last_name: LastName,
+    pub email: String,
+    pub created_at: DateTime<Utc>,
+    pub updated_at: DateTime<Utc>,
+    pub deleted_at: Option<DateTime<Utc>>,
+    pub version: i32,
+}
+
+#[derive(Deserialize)]
+pub struct PaginationParams {
+    pub limit: Option<u32>,
+    pub offset: Option<u32>,
+    pub cursor: Option<String>,
+}
+
+#[derive(Deserialize)]
+pub struct UserFilter {
+    pub last_name: Option<String>,
+}
+
+#[derive(Deserialize)]
+pub struct DeletedFilter {
+    #[serde(default)]
+    pub include_deleted: bool,
+}
+
+#[derive(Deserialize)]
+pub struct SearchParams {
+    pub q: Option<String>,
+}
+
+#[derive(Deserialize)]
+pub struct ExistsParams {
+    pub first_name: Option<String>,
+    pub last_name: Option<String>,
+}
+
+#[derive(Deserialize)]
+pub struct RecentParams {
+    /// RFC 3339 lower bound on `created_at`, 24 hours ago when absent.
+    pub since: Option<String>,
+    pub limit: Option<u32>,
+}
+
+const USER_FIELDS: [&str; 9] = [
+    "id",
+    "first_name",
+    "middle_name",
+    "last_name",
+    "email",
+    "created_at",
+    "updated_at",
+    "deleted_at",
+    "version",
+];
+
+#[derive(Deserialize)]
+pub struct FieldsParams {
+    pub fields: Option<String>,
+}
+
+impl FieldsParams {
+    /// Parses the comma-separated `fields` list; `None` selects every field.
+    pub fn selection(&self) -> Result<Option<Vec<&'static str>>, AppError> {
+        let Some(fields) = self.fields.as_deref() else {
+            return Ok(None);
+        };
+        let mut selected = Vec::new();
+        for name in fields.split(',').map(str::trim) {
+            let Some(&field) = USER_FIELDS.iter().find(|&&field| field == name) else {
+                return Err(AppError::Validation(format!(
+                    "unknown field '{name}', expected one of: {}",
+                    USER_FIELDS.join(", ")
+                )));
+            };
+            if !selected.contains(&field) {
+                selected.push(field);
+            }
+        }
+        Ok(Some(selected))
+    }
+}
+
+#[derive(Deserialize)]
+pub struct IncludeParams {
+    pub include: Option<String>,
+}
+
+impl IncludeParams {
+    /// Whether `?include=addresses` asks for the user's addresses to be embedded.
+    pub fn addresses(&self) -> Result<bool, AppError> {
+        match self.include.as_deref() {
+            None => Ok(false),
+            Some("addresses") => Ok(true),
+            Some(other) => Err(AppError::Validation(format!(
+                "unknown include '{other}', expected: addresses"
+            ))),
+        }
+    }
+}
+
+/// A user with either every field or only the ones picked by `?fields=`.
+#[derive(Serialize)]
+#[serde(untagged)]
+pub enum UserView {
+    Full(User),
+    Sparse(serde_json::Map<String, serde_json::Value>),
+}
+
+impl UserView {
+    pub fn new(user: User, fields: Option<&[&str]>) -> Self {
+        let Some(fields) = fields else {
+            return UserView::Full(user);
+        };
+        let mut all = match serde_json::to_value(user) {
+            Ok(serde_json::Value::Object(all)) => all,
+            _ => unreachable!("User always serializes to a JSON object"),
+        };
+        let sparse = fields
+            .iter()
+            .filter_map(|&field| all.remove_entry(field))
+            .collect();
+        UserView::Sparse(sparse)
+    }
+}
+
+#[derive(Clone, Copy, Default, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum SortField {
+    #[default]
+    Id,
+    FirstName,
+    LastName,
+}
+
+impl SortField {
+    pub fn column(self) -> &'static str {
+        match self {
+            SortField::Id => "id",
+            SortField::FirstName => "first_name",
+            SortField::LastName => "last_name",
+        }
+    }
+}
+
+#[derive(Clone, Copy, Default, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum SortOrder {
+    #[default]
+    Asc,
+    Desc,
+}
+
+impl SortOrder {
+    pub fn keyword(self) -> &'static str {
+        match self {
+            SortOrder::Asc => "ASC",
+            SortOrder::Desc => "DESC",
+        }
+    }
+}
+
+#[derive(Deserialize)]
+pub struct SortParams {
+    #[serde(default)]
+    pub sort: SortField,
+    #[serde(default)]
+    pub order: SortOrder,
+}
+
+#[derive(Serialize)]
+pub struct PagedResponse<T> {
+    /// Left out when the client sent `Prefer: count=none`.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub total_count: Option<i64>,
+    pub limit: u32,
+    pub offset: u32,
+    pub items: Vec<T>,
+}
+
+#[derive(Deserialize)]
+pub struct CursorPagination {
+    pub cursor: Option<String>,
+    pub limit: Option<u32>,
+}
+
+#[derive(Serialize)]
+pub struct CursorPagedResponse<T> {
+    pub items: Vec<T>,
+    pub next_cursor: Option<String>,
+}
+
+#[derive(Serialize)]
+pub struct ErrorResponse {
+    pub error: ErrorBody,
+}
+
+#[derive(Serialize)]
+pub struct ErrorBody {
+    pub code: &'static str,
+    pub message: String,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub trace_id: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub resource: Option<&'static str>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub id: Option<Uuid>,
+    #[serde(skip_serializing_if = "Vec::is_empty")]
+    pub errors: Vec<FieldError>,
+    /// Media types the resource can be served as, on a 406.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub supported: Option<&'static [&'static str]>,
+}
+
+#[derive(Serialize)]
+pub struct FieldError {
+    pub field: String,
+    pub message: String,
+}
+
+/// Parses a typed path parameter, rejecting it as `invalid_id` with the segment echoed.
+/// Every typed path extractor goes through this so malformed ids look the same everywhere.
+fn parse_path_param<T: FromStr>(segment: String) -> Result<T, AppError> {
+    segment.parse().map_err(|_| AppError::InvalidId(segment))
+}
+
+/// Whether to wrap the response as `{"data": ..., "meta": ...}`: `?envelope=` when given,
+/// else the `RESPONSE_ENVELOPE` setting.
+#[derive(Clone, Copy)]
+pub struct Envelope(pub bool);
+
+#[derive(Deserialize)]
+struct EnvelopeParams {
+    envelope: Option<bool>,
+}
+
+impl FromRequestParts<AppState> for Envelope {
+    type Rejection = AppError;
+
+    async fn from_request_parts(
+        parts: &mut Parts,
+        state: &AppState,
+    ) -> Result<Self, Self::Rejection> {
+        let params: EnvelopeParams =
+            serde_urlencoded::from_str(parts.uri.query().unwrap_or_default())
+                .map_err(|_| AppError::Validation("envelope must be true or false".to_owned()))?;
+        Ok(Envelope(params.envelope.unwrap_or(state.response_envelope)))
+    }
+}
+
+#[derive(Serialize)]
+pub struct EnvelopeBody<T> {
+    pub data: T,
+    pub meta: EnvelopeMeta,
+}
+
+/// Everything but the payload itself; fields that do not apply to a response are left out.
+#[derive(Default, Serialize)]
+pub struct EnvelopeMeta {
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub count: Option<usize>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub total_count: Option<i64>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub limit: Option<u32>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub offset: Option<u32>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub next_cursor: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub missing: Option<Vec<UserId>>,
+    pub trace_id: Option<String>,
+}
+
+/// Maximum request body size for a route, set next to its `DefaultBodyLimit`.
+#[derive(Clone, Copy)]
+pub struct BodyLimit(pub usize);
+
+/// JSON request body whose rejections all use the error envelope: 415 for a non-JSON
+/// `Content-Type`, 413 over the route's [`BodyLimit`] (checked against `Content-Length`
+/// before reading), and 400 naming the offending field when the body does not deserialize.
+pub struct JsonBody<T>(pub T);
+
+impl<S: Send + Sync, T: DeserializeOwned> FromRequest<S> for JsonBody<T> {
+    type Rejection = AppError;
+
+    async fn from_request(req: Request, state: &S) -> Result<Self, Self::Rejection> {
+        if !is_json_content_type(req.headers()) {
+            return Err(AppError::UnsupportedMediaType("application/json"));
+        }
+        let bytes = read_limited_body(req, state).await?;
+        let deserializer = &mut serde_json::Deserializer::from_slice(&bytes);
+        serde_path_to_error::deserialize(deserializer)
+            .map(JsonBody)
+            .map_err(invalid_json)
+    }
+}
+
+const FORM_CONTENT_TYPE: &str = "application/x-www-form-urlencoded";
+
+/// Like [`JsonBody`], but also accepts `application/x-www-form-urlencoded` bodies
+/// deserialized into the same type. The second field is the content type that was used.
+pub struct JsonOrForm<T>(pub T, pub &'static str);
+
+impl<S: Send + Sync, T: DeserializeOwned> FromRequest<S> for JsonOrForm<T> {
+    type Rejection = AppError;
+
+    async fn from_request(req: Request, state: &S) -> Result<Self, Self::Rejection> {
+        if is_json_content_type(req.headers()) {
+            let JsonBody(value) = JsonBody::from_request(req, state).await?;
+            return Ok(JsonOrForm(value, "application/json"));
+        }
+        if mime_type(req.headers()).as_deref() != Some(FORM_CONTENT_TYPE) {
+            return Err(AppError::UnsupportedMediaType(
+                "application/json or application/x-www-form-urlencoded",
+            ));
+        }
+        let bytes = read_limited_body(req, state).await?;
+        let deserializer = serde_urlencoded::Deserializer::new(form_urlencoded::parse(&bytes));
+        serde_path_to_error::deserialize(deserializer)
+            .map(|value| JsonOrForm(value, FORM_CONTENT_TYPE))
+            .map_err(|err| {
+                let path = err.path().to_string();
+                let message = err.into_inner().to_string();
+                let field = match quoted_field(&message) {
+                    Some(name) => name.to_owned(),
+                    None if path == "." => String::new(),
+                    None => path,
+                };
+                invalid_field(field, message)
+            })
+    }
+}
+
+const IMAGE_CONTENT_TYPES: [&str; 2] = ["image/png", "image/jpeg"];
+
+/// Raw PNG or JPEG request body with the content type it was sent as. Rejections match
+/// [`JsonBody`]: 415 for any other `Content-Type` and 413 over the route's [`BodyLimit`].
+pub struct ImageBody {
+    pub bytes: Bytes,
+    pub content_type: &'static str,
+}
+
+impl<S: Send + Sync> FromRequest<S> for ImageBody {
+    type Rejection = AppError;
+
+    async fn from_request(req: Request, state: &S) -> Result<Self, Self::Rejection> {
+        let mime = mime_type(req.headers());
+        let content_type = IMAGE_CONTENT_TYPES
+            .into_iter()
+            .find(|supported| mime.as_deref() == Some(*supported))
+            .ok_or(AppError::UnsupportedMediaType("image/png or image/jpeg"))?;
+        let bytes = read_limited_body(req, state).await?;
+        if bytes.is_empty() {
+            return Err(AppError::Validation(
+                "image body must not be empty".to_owned(),
+            ));
+        }
+        Ok(ImageBody {
+            bytes,
+            content_type,
+        })
+    }
+}
+
+/// Reads the body, rejecting a `Content-Length` over the route's [`BodyLimit`] up front.
+async fn read_limited_body<S: Send + Sync>(req: Request, state: &S) -> Result<Bytes, AppError> {
+    let limit = req.extensions().get::<BodyLimit>().copied();
+    if let Some(BodyLimit(limit)) = limit {
+        let content_length = req
+            .headers()
+            .get(header::CONTENT_LENGTH)
+            .and_then(|value| value.to_str().ok())
+            .and_then(|value| value.parse::<usize>().ok());
+        if content_length.is_some_and(|length| length > limit) {
+            return Err(AppError::PayloadTooLarge { limit });
+        }
+    }
+    match Bytes::from_request(req, state).await {
+        Ok(bytes) => Ok(bytes),
+        Err(rejection) if rejection.status() == StatusCode::PAYLOAD_TOO_LARGE => {
+            let limit = limit.map_or(0, |BodyLimit(limit)| limit);
+            Err(AppError::PayloadTooLarge { limit })
+        }
+        Err(rejection) => Err(AppError::InvalidBody {
+            message: rejection.body_text(),
+            errors: Vec::new(),
+        }),
+    }
+}
+
+fn mime_type(headers: &HeaderMap) -> Option<String> {
+    let content_type = headers.get(header::CONTENT_TYPE)?.to_str().ok()?;
+    Some(
+        content_type
+            .split(';')
+            .next()
+            .unwrap_or_default()
+            .trim()
+            .to_ascii_lowercase(),
+    )
+}
+
+fn is_json_content_type(headers: &HeaderMap) -> bool {
+    mime_type(headers).is_some_and(|mime| {
+        mime == "application/json" || (mime.starts_with("application/") && mime.ends_with("+json"))
+    })
+}
+
+/// The field named in serde's "missing field `x`" or "unknown field `x`, ..." messages.
+fn quoted_field(message: &str) -> Option<&str> {
+    message
+        .strip_prefix("missing field `")
+        .or_else(|| message.strip_prefix("unknown field `"))
+        .and_then(|rest| rest.split('`').next())
+}
+
+fn invalid_json(err: serde_path_to_error::Error<serde_json::Error>) -> AppError {
+    let path = err.path().to_string();
+    let err = err.into_inner();
+    if !err.is_data() {
+        return AppError::InvalidBody {
+            message: format!("request body is not valid JSON: {err}"),
+            errors: Vec::new(),
+        };
+    }
+    // serde_json appends the position to the message; the field name is more useful here.
+    let message = err.to_string();
+    let message = message
+        .strip_suffix(&format!(" at line {} column {}", err.line(), err.column()))
+        .unwrap_or(&message)
+        .to_owned();
+    // A missing field is reported against the enclosing object, so take its name from
+    // serde's message; unknown fields already appear in the path.
+    let missing = quoted_field(&message).filter(|_| message.starts_with("missing field"));
+    let field = match missing {
+        Some(name) if path == "." => name.to_owned(),
+        Some(name) => format!("{path}.{name}"),
+        None => path,
+    };
+    invalid_field(field, message)
+}
+
+/// A field that failed to deserialize: 422 when a name broke its rules, as if it had
+/// been validated after extraction, and 400 for any other shape mismatch.
+fn invalid_field(field: String, message: String) -> AppError {
+    if NameError::is_name_error(&message) {
+        return AppError::InvalidFields(vec![FieldError { field, message }]);
+    }
+    AppError::InvalidBody {
+        message: "request body does not match the expected shape".to_owned(),
+        errors: vec![FieldError { field, message }],
+    }
+}
+
+/// A `{id}/.../{child_id}` pair of path parameters: a user and the id of something it
+/// owns, both checked like a [`UserId`] path parameter.
+pub struct ValidUuidPair(pub UserId, pub Uuid);
+
+impl<S: Send + Sync> FromRequestParts<S> for ValidUuidPair {
+    type Rejection = AppError;
+
+    async fn from_request_parts(parts: &mut Parts, state: &S) -> Result<Self, Self::Rejection> {
+        let Path((first, second)) = Path::<(String, String)>::from_request_parts(parts, state)
+            .await
+            .map_err(|rejection| AppError::Validation(rejection.body_text()))?;
+        Ok(ValidUuidPair(
+            parse_path_param(first)?,
+            parse_path_param(second)?,
+        ))
+    }
+}
+
+/// A user plus the addresses requested with `?include=addresses`.
+#[derive(Serialize)]
+pub struct UserWithAddresses {
+    #[serde(flatten)]
+    pub user: UserView,
+    pub addresses: Vec<Address>,
+}
+
+#[derive(Serialize)]
+pub struct Address {
     pub id: Uuid,
-    pub first_name: String,
+    pub user_id: UserId,
+    pub line1: String,
+    pub line2: Option<String>,
+    pub city: String,
+    pub postal_code: String,
+    pub country: String,
+    pub created_at: DateTime<Utc>,
+}
+
+/// One row of `user_audit`. `old_values` is absent for creations.
+#[derive(Serialize)]
+pub struct AuditEntry {
+    pub id: i64,
+    pub user_id: UserId,
+    pub operation: String,
+    pub old_values: Option<serde_json::Value>,
+    pub new_values: serde_json::Value,
+    pub trace_id: Option<String>,
+    pub created_at: DateTime<Utc>,
+}
+
+/// Aggregates served by `GET /users/stats`, counting only users that are not soft-deleted.
+#[derive(Serialize)]
+pub struct UserStats {
+    pub total: i64,
+    /// Every UTC day of the last 30, oldest first, including days without signups.
+    pub created_per_day: Vec<DailyCount>,
+    pub top_last_names: Vec<LastNameCount>,
+}
+
+#[derive(Serialize)]
+pub struct DailyCount {
+    /// `YYYY-MM-DD`.
+    pub date: String,
+    pub count: i64,
+}
+
+#[derive(Serialize)]
+pub struct LastNameCount {
     pub last_name: String,
+    pub count: i64,
 }
 
-#[derive(Deserialize)]
+#[derive(Serialize)]
+pub struct CountResponse {
+    pub count: i64,
+}
+
+#[derive(Serialize)]
+pub struct ExistsResponse {
+    pub exists: bool,
+    pub count: i64,
+    /// The oldest matches, capped at `MAX_EXISTS_IDS`.
+    pub ids: Vec<UserId>,
+}
+
+#[derive(Serialize)]
+pub struct LookupResponse {
+    pub items: Vec<User>,
+    pub missing: Vec<UserId>,
+}
+
+#[derive(Serialize)]
+pub struct BulkDeleteResponse {
+    pub requested: usize,
+    pub deleted: u64,
+}
+
+/// The names are also checked while the body is read, so their `garde` rules only
+/// matter for requests built in code.
+#[derive(Deserialize, garde::Validate)]
+#[serde(deny_unknown_fields)]
 pub struct CreateUserRequest {
-    pub first_name: String,
-    pub last_name: String,
+    #[garde(length(chars, min = 1, max = MAX_NAME_LEN))]
+    pub first_name: FirstName,
+    /// Absent and `null` both store no middle name; an empty string fails validation.
+    #[garde(length(chars, min = 1, max = MAX_NAME_LEN))]
+    pub middle_name: Option<MiddleName>,
+    #[garde(length(chars, min = 1, max = MAX_NAME_LEN))]
+    pub last_name: LastName,
+    #[garde(custom(check_email))]
+    pub email: String,
+}
+
+const MAX_NAME_LEN: usize = 100;
+/// The longest address SMTP can deliver to (RFC 5321).
+const MAX_EMAIL_LEN: usize = 254;
+
+impl CreateUserRequest {
+    /// Runs the `garde` rules and reports each failure as a [`FieldError`].
+    pub fn validate(&self) -> Result<(), Vec<FieldError>> {
+        garde::Validate::validate(self).map_err(|report| {
+            report
+                .iter()
+                .map(|(path, error)| FieldError {
+                    field: path.to_string(),
+                    message: error.message().to_owned(),
+                })
+                .collect()
+        })
+    }
+}
+
+fn check_email(value: &str, _context: &()) -> garde::Result {
+    if is_email(value) {
+        Ok(())
+    } else {
+        Err(garde::Error::new(
+            "must be an email address such as \"ada@example.com\"",
+        ))
+    }
+}
+
+/// A deliberately loose check: one `@` between a non-empty local part and a dotted
+/// domain, no whitespace. Whether the mailbox exists is only known by writing to it.
+fn is_email(value: &str) -> bool {
+    let Some((local, domain)) = value.split_once('@') else {
+        return false;
+    };
+    value.len() <= MAX_EMAIL_LEN
+        && !value.chars().any(|c| c.is_whitespace() || c.is_control())
+        && !local.is_empty()
+        && !domain.contains('@')
+        && domain.split('.').count() >= 2
+        && domain.split('.').all(|label| !label.is_empty())
+}
+
+fn validate_name(field: &str, value: &str, errors: &mut Vec<FieldError>) {
+    if let Err(err) = NameError::check(value) {
+        errors.push(FieldError {
+            field: field.to_owned(),
+            message: err.to_string(),
+        });
+    }
+}
+
+fn validate_optional_name(field: &str, value: Option<&str>, errors: &mut Vec<FieldError>) {
+    if let Some(value) = value {
+        validate_name(field, value, errors);
+    }
+}
+
+fn into_result(errors: Vec<FieldError>) -> Result<(), Vec<FieldError>> {
+    if errors.is_empty() {
+        Ok(())
+    } else {
+        Err(errors)
+    }
+}
+
+#[derive(Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct CreateAddressRequest {
+    pub line1: String,
+    pub line2: Option<String>,
+    pub city: String,
+    pub postal_code: String,
+    pub country: String,
+}
+
+impl CreateAddressRequest {
+    pub fn validate(&self) -> Result<(), Vec<FieldError>> {
+        let mut errors = Vec::new();
+        validate_name("line1", &self.line1, &mut errors);
+        validate_optional_name("line2", self.line2.as_deref(), &mut errors);
+        validate_name("city", &self.city, &mut errors);
+        validate_name("postal_code", &self.postal_code, &mut errors);
+        if self.country.len() != 2 || !self.country.bytes().all(|b| b.is_ascii_uppercase()) {
+            errors.push(FieldError {
+                field: "country".to_owned(),
+                message: "must be an ISO 3166-1 alpha-2 code such as \"DE\"".to_owned(),
+            });
+        }
+        into_result(errors)
+    }
+}
+
+/// Body of `POST /users/merge`: `remove` is folded into `keep` and soft-deleted.
+#[derive(Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct MergeUsersRequest {
+    pub keep: UserId,
+    pub remove: UserId,
+}
+
+/// Body of `PUT /user/{id}`: every editable field, replacing what is stored.
+#[derive(Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct UpdateUserRequest {
+    pub first_name: FirstName,
+    /// Absent and `null` both clear the middle name.
+    pub middle_name: Option<MiddleName>,
+    pub last_name: LastName,
+    pub version: Option<i32>,
+}
+
+/// Body of `PATCH /user/{id}`: only the fields sent change.
+#[derive(Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct PatchUserRequest {
+    pub first_name: Option<FirstName>,
+    /// Absent keeps the middle name and `null` clears it.
+    #[serde(default, deserialize_with = "present")]
+    pub middle_name: Option<Option<MiddleName>>,
+    pub last_name: Option<LastName>,
+    pub version: Option<i32>,
+}
+
+/// Reads a field that was sent, `null` included, as `Some`, so that together with
+/// `#[serde(default)]` only an absent field is `None`.
+fn present<'de, D, T>(deserializer: D) -> Result<Option<T>, D::Error>
+where
+    D: Deserializer<'de>,
+    T: Deserialize<'de>,
+{
+    T::deserialize(deserializer).map(Some)
 }
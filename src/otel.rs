@@ -1,34 +1,662 @@
-use anyhow::Context;
-use opentelemetry_otlp::{MetricExporter, SpanExporter};
-use opentelemetry_sdk::{Resource, metrics::SdkMeterProvider, trace::SdkTracerProvider};
+use std::env;
+use std::str::FromStr;
+use std::time::{Duration, Instant};
+
+use anyhow::{Context, bail, ensure};
+use opentelemetry::metrics::{Histogram, Meter, UpDownCounter};
+use opentelemetry::propagation::{TextMapCompositePropagator, TextMapPropagator};
+use opentelemetry::{KeyValue, global};
+use opentelemetry_otlp::tonic_types::transport::{Certificate, ClientTlsConfig, Identity};
+use opentelemetry_otlp::{
+    LogExporter, MetricExporter, Protocol, SpanExporter, WithExportConfig, WithTonicConfig,
+};
+use opentelemetry_sdk::{
+    Resource,
+    logs::SdkLoggerProvider,
+    metrics::{PeriodicReader, SdkMeterProvider, Temporality},
+    propagation::{BaggagePropagator, TraceContextPropagator},
+    resource::{EnvResourceDetector, ResourceBuilder, TelemetryResourceDetector},
+    trace::{BatchConfigBuilder, BatchSpanProcessor, Sampler, SdkTracerProvider},
+};
+#[cfg(feature = "zipkin")]
+use opentelemetry_zipkin::{B3Encoding, Propagator as B3Propagator, ZipkinExporter};
+use tracing_subscriber::filter::{LevelFilter, Targets};
+
+use crate::middleware::TenantIdProcessor;
 
 pub struct Providers {
     pub tracer: SdkTracerProvider,
     pub meter: SdkMeterProvider,
+    /// Receives `tracing` events through the appender bridge; exports nothing without OTLP.
+    pub log_provider: SdkLoggerProvider,
+    /// Collector the OTLP exporters send to, `None` when printing to stdout instead.
+    pub otlp_endpoint: Option<String>,
+    /// The sampler picked from `OTEL_TRACES_SAMPLER`, e.g. `traceidratio(0.25)`.
+    pub sampler: String,
+    /// The propagators installed from `OTEL_PROPAGATORS`, e.g. `tracecontext,baggage`.
+    pub propagators: String,
+    /// Batch span processor settings, `None` when spans are printed without batching.
+    pub span_batch: Option<SpanBatchConfig>,
+    pub metric_export: MetricExportConfig,
+    /// Configuration problems found before logging was set up, for `main` to report.
+    pub warnings: Vec<String>,
+    /// Registry the `/metrics` endpoint gathers from.
+    #[cfg(feature = "prometheus")]
+    pub prometheus: prometheus::Registry,
 }
 
-pub fn init_providers() -> anyhow::Result<Providers> {
-    let resource = Resource::builder().with_service_name("rust-telemetry").build();
+/// Batch span processor limits from the `OTEL_BSP_*` variables.
+pub struct SpanBatchConfig {
+    /// Spans buffered before new ones are dropped.
+    pub max_queue_size: usize,
+    pub max_export_batch_size: usize,
+    pub export_timeout: Duration,
+}
+
+impl SpanBatchConfig {
+    fn from_env() -> anyhow::Result<Self> {
+        let config = Self {
+            max_queue_size: env_or("OTEL_BSP_MAX_QUEUE_SIZE", 2048)?,
+            max_export_batch_size: env_or("OTEL_BSP_MAX_EXPORT_BATCH_SIZE", 512)?,
+            export_timeout: Duration::from_millis(env_or("OTEL_BSP_EXPORT_TIMEOUT", 30_000)?),
+        };
+        ensure!(
+            config.max_queue_size > 0,
+            "OTEL_BSP_MAX_QUEUE_SIZE must be a positive integer"
+        );
+        ensure!(
+            (1..=config.max_queue_size).contains(&config.max_export_batch_size),
+            "OTEL_BSP_MAX_EXPORT_BATCH_SIZE must be between 1 and OTEL_BSP_MAX_QUEUE_SIZE"
+        );
+        ensure!(
+            !config.export_timeout.is_zero(),
+            "OTEL_BSP_EXPORT_TIMEOUT must be a positive integer"
+        );
+        Ok(config)
+    }
+}
+
+/// Periodic metric export settings from `OTEL_METRIC_EXPORT_INTERVAL` and `OTEL_METRIC_EXPORT_TIMEOUT`.
+pub struct MetricExportConfig {
+    pub interval: Duration,
+    /// Only applies to the OTLP exporter.
+    pub timeout: Duration,
+    /// From `OTEL_EXPORTER_OTLP_METRICS_TEMPORALITY_PREFERENCE`, only applies to the OTLP exporter.
+    pub temporality: Temporality,
+}
+
+impl MetricExportConfig {
+    fn from_env() -> anyhow::Result<Self> {
+        let config = Self {
+            interval: Duration::from_millis(env_or("OTEL_METRIC_EXPORT_INTERVAL", 60_000)?),
+            timeout: Duration::from_millis(env_or("OTEL_METRIC_EXPORT_TIMEOUT", 30_000)?),
+            temporality: metrics_temporality()?,
+        };
+        ensure!(
+            !config.interval.is_zero(),
+            "OTEL_METRIC_EXPORT_INTERVAL must be a positive integer"
+        );
+        ensure!(
+            !config.timeout.is_zero(),
+            "OTEL_METRIC_EXPORT_TIMEOUT must be a positive integer"
+        );
+        Ok(config)
+    }
+}
+
+/// Cumulative points carry the running total since startup, so a lost export costs
+/// nothing and a restart shows up as a reset; the SDK keeps every series in memory for
+/// the life of the process. Delta points carry only the change since the last export,
+/// which suits backends such as Datadog that sum on ingest and lets the SDK forget idle
+/// series, but a failed export loses that interval's data for good.
+fn metrics_temporality() -> anyhow::Result<Temporality> {
+    match env::var("OTEL_EXPORTER_OTLP_METRICS_TEMPORALITY_PREFERENCE").as_deref() {
+        Err(_) | Ok("cumulative") => Ok(Temporality::Cumulative),
+        Ok("delta") => Ok(Temporality::Delta),
+        Ok(other) => bail!(
+            "Unsupported OTEL_EXPORTER_OTLP_METRICS_TEMPORALITY_PREFERENCE {other:?}, \
+             expected \"cumulative\" or \"delta\""
+        ),
+    }
+}
+
+/// Parses `name` if it is set, so that a typo fails startup instead of silently
+/// falling back to the SDK default.
+fn env_or<T: FromStr>(name: &str, default: T) -> anyhow::Result<T> {
+    match env::var(name) {
+        Ok(value) => value
+            .parse()
+            .map_err(|_| anyhow::anyhow!("{name} must be a non-negative integer, got {value:?}")),
+        Err(_) => Ok(default),
+    }
+}
+
+enum OtlpProtocol {
+    Grpc,
+    HttpProtobuf,
+}
+
+fn otlp_protocol() -> anyhow::Result<OtlpProtocol> {
+    match env::var("OTEL_EXPORTER_OTLP_PROTOCOL").as_deref() {
+        Err(_) | Ok("grpc") => Ok(OtlpProtocol::Grpc),
+        Ok("http/protobuf") => Ok(OtlpProtocol::HttpProtobuf),
+        Ok(other) => bail!(
+            "Unsupported OTEL_EXPORTER_OTLP_PROTOCOL {other:?}, expected \"grpc\" or \"http/protobuf\""
+        ),
+    }
+}
 
-    let span_exporter = SpanExporter::builder()
-        .with_tonic()
+/// Sampler from `OTEL_TRACES_SAMPLER` and `OTEL_TRACES_SAMPLER_ARG`, with a description of
+/// it. Unknown samplers fall back to `always_on` and bad ratios to 1.0, with a warning.
+fn sampler(warnings: &mut Vec<String>) -> (Sampler, String) {
+    let name = env::var("OTEL_TRACES_SAMPLER").unwrap_or_else(|_| "always_on".to_owned());
+    let mut ratio = || {
+        let arg = env::var("OTEL_TRACES_SAMPLER_ARG").ok();
+        match arg.as_deref().map(str::parse::<f64>) {
+            Some(Ok(ratio)) if (0.0..=1.0).contains(&ratio) => ratio,
+            Some(_) => {
+                warnings.push(format!(
+                    "OTEL_TRACES_SAMPLER_ARG {:?} is not a ratio between 0 and 1, sampling everything",
+                    arg.unwrap_or_default()
+                ));
+                1.0
+            }
+            None => {
+                warnings.push("OTEL_TRACES_SAMPLER_ARG is not set, sampling everything".to_owned());
+                1.0
+            }
+        }
+    };
+    match name.as_str() {
+        "always_on" => (Sampler::AlwaysOn, name),
+        "always_off" => (Sampler::AlwaysOff, name),
+        "traceidratio" => {
+            let ratio = ratio();
+            (
+                Sampler::TraceIdRatioBased(ratio),
+                format!("{name}({ratio})"),
+            )
+        }
+        "parentbased_traceidratio" => {
+            let ratio = ratio();
+            (
+                Sampler::ParentBased(Box::new(Sampler::TraceIdRatioBased(ratio))),
+                format!("{name}({ratio})"),
+            )
+        }
+        _ => {
+            warnings.push(format!(
+                "Unsupported OTEL_TRACES_SAMPLER {name:?}, falling back to always_on"
+            ));
+            (Sampler::AlwaysOn, "always_on".to_owned())
+        }
+    }
+}
+
+/// TLS settings for the gRPC exporters: a CA from `OTEL_EXPORTER_OTLP_CERTIFICATE` and, for
+/// mTLS, a client identity from `OTEL_EXPORTER_OTLP_CLIENT_CERTIFICATE` and
+/// `OTEL_EXPORTER_OTLP_CLIENT_KEY`. `None` when none of them are set.
+fn tonic_tls_config() -> anyhow::Result<Option<ClientTlsConfig>> {
+    let read_pem = |name: &str| -> anyhow::Result<Option<Vec<u8>>> {
+        match env::var(name) {
+            Ok(path) => std::fs::read(&path)
+                .with_context(|| format!("Failed to read {name} {path:?}"))
+                .map(Some),
+            Err(_) => Ok(None),
+        }
+    };
+    let ca = read_pem("OTEL_EXPORTER_OTLP_CERTIFICATE")?;
+    let identity = match (
+        read_pem("OTEL_EXPORTER_OTLP_CLIENT_CERTIFICATE")?,
+        read_pem("OTEL_EXPORTER_OTLP_CLIENT_KEY")?,
+    ) {
+        (Some(cert), Some(key)) => Some(Identity::from_pem(cert, key)),
+        (None, None) => None,
+        _ => bail!(
+            "OTEL_EXPORTER_OTLP_CLIENT_CERTIFICATE and OTEL_EXPORTER_OTLP_CLIENT_KEY must be set together"
+        ),
+    };
+    if ca.is_none() && identity.is_none() {
+        return Ok(None);
+    }
+    let mut config = ClientTlsConfig::new();
+    if let Some(ca) = ca {
+        config = config.ca_certificate(Certificate::from_pem(ca));
+    }
+    if let Some(identity) = identity {
+        config = config.identity(identity);
+    }
+    Ok(Some(config))
+}
+
+/// Without a custom config, tonic's defaults apply to the channel.
+fn with_tls<B: WithTonicConfig>(builder: B, tls_config: Option<&ClientTlsConfig>) -> B {
+    match tls_config {
+        Some(tls_config) => builder.with_tls_config(tls_config.clone()),
+        None => builder,
+    }
+}
+
+/// Propagators from the comma-separated `OTEL_PROPAGATORS`, `tracecontext,baggage` by
+/// default, with the names that were used. `none` disables propagation and unknown names
+/// are skipped with a warning.
+fn propagator(warnings: &mut Vec<String>) -> (TextMapCompositePropagator, String) {
+    let value = env::var("OTEL_PROPAGATORS").unwrap_or_else(|_| "tracecontext,baggage".to_owned());
+    let mut propagators: Vec<Box<dyn TextMapPropagator + Send + Sync>> = Vec::new();
+    let mut names = Vec::new();
+    for name in value
+        .split(',')
+        .map(str::trim)
+        .filter(|name| !name.is_empty())
+    {
+        let propagator: Box<dyn TextMapPropagator + Send + Sync> = match name {
+            "tracecontext" => Box::new(TraceContextPropagator::new()),
+            "baggage" => Box::new(BaggagePropagator::new()),
+            #[cfg(feature = "zipkin")]
+            "b3" => Box::new(B3Propagator::with_encoding(B3Encoding::SingleHeader)),
+            #[cfg(feature = "zipkin")]
+            "b3multi" => Box::new(B3Propagator::with_encoding(B3Encoding::MultipleHeader)),
+            #[cfg(not(feature = "zipkin"))]
+            "b3" | "b3multi" => {
+                warnings.push(format!(
+                    "Propagator {name:?} requires building with --features zipkin, skipping it"
+                ));
+                continue;
+            }
+            "none" => continue,
+            _ => {
+                warnings.push(format!(
+                    "Unsupported propagator {name:?} in OTEL_PROPAGATORS, skipping it"
+                ));
+                continue;
+            }
+        };
+        propagators.push(propagator);
+        names.push(name);
+    }
+    let description = if names.is_empty() {
+        "none".to_owned()
+    } else {
+        names.join(",")
+    };
+    (TextMapCompositePropagator::new(propagators), description)
+}
+
+/// Where spans go, from `TRACE_EXPORTER`: OTLP (the default) or Zipkin.
+enum TraceExporter {
+    Otlp,
+    #[cfg(feature = "zipkin")]
+    Zipkin,
+}
+
+fn trace_exporter() -> anyhow::Result<TraceExporter> {
+    match env::var("TRACE_EXPORTER").as_deref() {
+        Err(_) | Ok("otlp") => Ok(TraceExporter::Otlp),
+        #[cfg(feature = "zipkin")]
+        Ok("zipkin") => Ok(TraceExporter::Zipkin),
+        #[cfg(not(feature = "zipkin"))]
+        Ok("zipkin") => bail!("TRACE_EXPORTER=zipkin requires building with --features zipkin"),
+        Ok(other) => bail!("Unsupported TRACE_EXPORTER {other:?}, expected \"otlp\" or \"zipkin\""),
+    }
+}
+
+/// Zipkin exporter for `ZIPKIN_ENDPOINT`, e.g. `http://zipkin:9411/api/v2/spans`.
+/// Requests are bounded by `OTEL_BSP_EXPORT_TIMEOUT`, like the OTLP span exporters.
+#[cfg(feature = "zipkin")]
+fn zipkin_exporter(export_timeout: Duration) -> anyhow::Result<ZipkinExporter> {
+    let endpoint = env::var("ZIPKIN_ENDPOINT")
+        .context("ZIPKIN_ENDPOINT must be set when TRACE_EXPORTER=zipkin")?;
+    // A blocking HTTP client must not be created on a runtime thread.
+    std::thread::spawn(move || -> anyhow::Result<ZipkinExporter> {
+        let client = reqwest::blocking::Client::builder()
+            .timeout(export_timeout)
+            .build()
+            .context("Failed to create Zipkin HTTP client")?;
+        ZipkinExporter::builder()
+            .with_collector_endpoint(endpoint)
+            .with_http_client(client)
+            .build()
+            .context("Failed to create Zipkin exporter")
+    })
+    .join()
+    .map_err(|_| anyhow::anyhow!("Zipkin exporter construction panicked"))?
+}
+
+fn batch_processor<E: opentelemetry_sdk::trace::SpanExporter + 'static>(
+    exporter: E,
+    config: &SpanBatchConfig,
+) -> BatchSpanProcessor {
+    BatchSpanProcessor::builder(exporter)
+        .with_batch_config(
+            BatchConfigBuilder::default()
+                .with_max_queue_size(config.max_queue_size)
+                .with_max_export_batch_size(config.max_export_batch_size)
+                .build(),
+        )
         .build()
-        .context("Failed to create OTLP span exporter")?;
+}
+
+/// `OTEL_EXPORTER_OTLP_ENDPOINT` split into `scheme://host:port` and an optional path prefix.
+struct OtlpEndpoint {
+    origin: String,
+    path_prefix: String,
+}
+
+impl OtlpEndpoint {
+    fn parse(value: &str) -> anyhow::Result<Self> {
+        let value = value.trim();
+        let Some((scheme, rest)) = value.split_once("://") else {
+            bail!("OTEL_EXPORTER_OTLP_ENDPOINT {value:?} must start with http:// or https://");
+        };
+        if scheme != "http" && scheme != "https" {
+            bail!("OTEL_EXPORTER_OTLP_ENDPOINT {value:?} must start with http:// or https://");
+        }
+        let (authority, path) = rest.split_at(rest.find('/').unwrap_or(rest.len()));
+        if authority.is_empty() {
+            bail!("OTEL_EXPORTER_OTLP_ENDPOINT {value:?} has no host");
+        }
+        Ok(Self {
+            origin: format!("{scheme}://{authority}"),
+            path_prefix: path.trim_end_matches('/').to_owned(),
+        })
+    }
+
+    /// gRPC ignores the path; over HTTP the spec appends `/v1/{signal}` to the prefix.
+    fn signal_url(&self, protocol: &OtlpProtocol, signal: &str) -> String {
+        match protocol {
+            OtlpProtocol::Grpc => self.origin.clone(),
+            OtlpProtocol::HttpProtobuf => {
+                format!("{}{}/v1/{signal}", self.origin, self.path_prefix)
+            }
+        }
+    }
+}
+
+impl std::fmt::Display for OtlpEndpoint {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{}{}", self.origin, self.path_prefix)
+    }
+}
+
+trait ResourceBuilderExt {
+    fn with_host_info(self) -> Self;
+}
+
+impl ResourceBuilderExt for ResourceBuilder {
+    /// Adds `host.name`, `os.type` and `process.pid` for the running process.
+    fn with_host_info(self) -> Self {
+        let os_type = match env::consts::OS {
+            "macos" => "darwin",
+            os => os,
+        };
+        self.with_attributes([
+            KeyValue::new(
+                "host.name",
+                gethostname::gethostname().to_string_lossy().into_owned(),
+            ),
+            KeyValue::new("os.type", os_type),
+            KeyValue::new("process.pid", i64::from(std::process::id())),
+        ])
+    }
+}
+
+/// Resource attributes: `telemetry.sdk.*` and host info, then `OTEL_RESOURCE_ATTRIBUTES`,
+/// then the service name, which is `OTEL_SERVICE_NAME` if set, else `service.name` from the
+/// attribute list, else "rust-telemetry". Later sources win on conflicting keys.
+fn resource() -> Resource {
+    let builder = Resource::builder_empty()
+        .with_service_name("rust-telemetry")
+        .with_detector(Box::new(TelemetryResourceDetector))
+        .with_host_info()
+        .with_detector(Box::new(EnvResourceDetector::new()));
+    match env::var("OTEL_SERVICE_NAME") {
+        Ok(name) if !name.is_empty() => builder.with_service_name(name),
+        _ => builder,
+    }
+    .build()
+}
+
+pub fn init_providers() -> anyhow::Result<Providers> {
+    let resource = resource();
+    let mut warnings = Vec::new();
+    let (sampler, sampler_description) = sampler(&mut warnings);
+    let metric_export = MetricExportConfig::from_env()?;
+    // OtelAxumLayer extracts the parent context, and OtelInResponseLayer injects the
+    // response header, through the global propagator.
+    let (propagator, propagators) = propagator(&mut warnings);
+    global::set_text_map_propagator(propagator);
 
     let tracer = SdkTracerProvider::builder()
-        .with_batch_exporter(span_exporter)
         .with_resource(resource.clone())
-        .build();
+        .with_sampler(sampler)
+        .with_span_processor(TenantIdProcessor);
+    let meter = SdkMeterProvider::builder().with_resource(resource.clone());
+    let logger = SdkLoggerProvider::builder().with_resource(resource);
+
+    // Zipkin only replaces the trace pipeline; metrics still follow OTEL_EXPORTER_OTLP_ENDPOINT.
+    let trace_exporter = trace_exporter()?;
+
+    // Without a collector endpoint, print telemetry locally instead of failing to export.
+    let (tracer, meter, logger, otlp_endpoint, span_batch) =
+        if let Ok(endpoint) = env::var("OTEL_EXPORTER_OTLP_ENDPOINT") {
+            let endpoint = OtlpEndpoint::parse(&endpoint)?;
+            let protocol = otlp_protocol()?;
+            let span_batch = SpanBatchConfig::from_env()?;
+            let tls_config = match protocol {
+                OtlpProtocol::Grpc => tonic_tls_config()?,
+                OtlpProtocol::HttpProtobuf => None,
+            };
+            let traces_url = endpoint.signal_url(&protocol, "traces");
+            let metrics_url = endpoint.signal_url(&protocol, "metrics");
+            let logs_url = endpoint.signal_url(&protocol, "logs");
+
+            // The SDK's own export timeout is only honoured by its async-runtime processor,
+            // so the timeout is enforced by the OTLP exporters instead.
+            let span_processor = match trace_exporter {
+                #[cfg(feature = "zipkin")]
+                TraceExporter::Zipkin => {
+                    batch_processor(zipkin_exporter(span_batch.export_timeout)?, &span_batch)
+                }
+                TraceExporter::Otlp => {
+                    let span_exporter = match protocol {
+                        OtlpProtocol::Grpc => {
+                            with_tls(SpanExporter::builder().with_tonic(), tls_config.as_ref())
+                                .with_endpoint(traces_url)
+                                .with_timeout(span_batch.export_timeout)
+                                .build()
+                        }
+                        OtlpProtocol::HttpProtobuf => SpanExporter::builder()
+                            .with_http()
+                            .with_protocol(Protocol::HttpBinary)
+                            .with_endpoint(traces_url)
+                            .with_timeout(span_batch.export_timeout)
+                            .build(),
+                    }
+                    .context("Failed to create OTLP span exporter")?;
+                    batch_processor(span_exporter, &span_batch)
+                }
+            };
+
+            let metric_exporter = match protocol {
+                OtlpProtocol::Grpc => with_tls(
+                    MetricExporter::builder()
+                        .with_temporality(metric_export.temporality)
+                        .with_tonic(),
+                    tls_config.as_ref(),
+                )
+                .with_endpoint(metrics_url)
+                .with_timeout(metric_export.timeout)
+                .build(),
+                OtlpProtocol::HttpProtobuf => MetricExporter::builder()
+                    .with_temporality(metric_export.temporality)
+                    .with_http()
+                    .with_protocol(Protocol::HttpBinary)
+                    .with_endpoint(metrics_url)
+                    .with_timeout(metric_export.timeout)
+                    .build(),
+            }
+            .context("Failed to create OTLP metric exporter")?;
+
+            let log_exporter = match protocol {
+                OtlpProtocol::Grpc => {
+                    with_tls(LogExporter::builder().with_tonic(), tls_config.as_ref())
+                        .with_endpoint(logs_url)
+                        .build()
+                }
+                OtlpProtocol::HttpProtobuf => LogExporter::builder()
+                    .with_http()
+                    .with_protocol(Protocol::HttpBinary)
+                    .with_endpoint(logs_url)
+                    .build(),
+            }
+            .context("Failed to create OTLP log exporter")?;
+
+            (
+                tracer.with_span_processor(span_processor),
+                meter.with_reader(
+                    PeriodicReader::builder(metric_exporter)
+                        .with_interval(metric_export.interval)
+                        .build(),
+                ),
+                logger.with_batch_exporter(log_exporter),
+                Some(endpoint.to_string()),
+                Some(span_batch),
+            )
+        } else {
+            let (tracer, span_batch) = match trace_exporter {
+                #[cfg(feature = "zipkin")]
+                TraceExporter::Zipkin => {
+                    let span_batch = SpanBatchConfig::from_env()?;
+                    let processor =
+                        batch_processor(zipkin_exporter(span_batch.export_timeout)?, &span_batch);
+                    (tracer.with_span_processor(processor), Some(span_batch))
+                }
+                TraceExporter::Otlp => (
+                    tracer.with_simple_exporter(opentelemetry_stdout::SpanExporter::default()),
+                    None,
+                ),
+            };
+            (
+                tracer,
+                meter.with_reader(
+                    PeriodicReader::builder(opentelemetry_stdout::MetricExporter::default())
+                        .with_interval(metric_export.interval)
+                        .build(),
+                ),
+                // The fmt layer already prints every event to stderr, so logs are not exported.
+                logger,
+                None,
+                span_batch,
+            )
+        };
 
-    let metric_exporter = MetricExporter::builder()
-        .with_tonic()
+    #[cfg(feature = "prometheus")]
+    let prometheus = prometheus::Registry::new();
+    #[cfg(feature = "prometheus")]
+    let meter = meter.with_reader(
+        opentelemetry_prometheus::exporter()
+            .with_registry(prometheus.clone())
+            .build()
+            .context("Failed to create Prometheus exporter")?,
+    );
+
+    Ok(Providers {
+        tracer: tracer.build(),
+        meter: meter.build(),
+        log_provider: logger.build(),
+        otlp_endpoint,
+        sampler: sampler_description,
+        propagators,
+        span_batch,
+        metric_export,
+        warnings,
+        #[cfg(feature = "prometheus")]
+        prometheus,
+    })
+}
+
+/// Events the log bridge exports. The exporters log through `tracing` themselves, so
+/// their HTTP and gRPC stacks are left out to keep an export from triggering another.
+pub fn log_bridge_filter() -> Targets {
+    Targets::new()
+        .with_default(LevelFilter::TRACE)
+        .with_targets(
+            [
+                "opentelemetry",
+                "opentelemetry_sdk",
+                "opentelemetry_otlp",
+                "hyper",
+                "h2",
+                "tonic",
+                "tower",
+                "reqwest",
+            ]
+            .map(|target| (target, LevelFilter::OFF)),
+        )
+}
+
+pub fn http_request_duration_histogram(meter: &Meter) -> Histogram<f64> {
+    meter
+        .f64_histogram("http.server.request.duration")
+        .with_unit("s")
+        .with_description("Duration of HTTP server requests")
+        .with_boundaries(vec![
+            0.005, 0.01, 0.025, 0.05, 0.075, 0.1, 0.25, 0.5, 0.75, 1.0, 2.5, 5.0, 7.5, 10.0,
+        ])
         .build()
-        .context("Failed to create OTLP metric exporter")?;
+}
 
-    let meter = SdkMeterProvider::builder()
-        .with_periodic_exporter(metric_exporter)
-        .with_resource(resource)
-        .build();
+pub fn db_operation_duration_histogram(meter: &Meter) -> Histogram<f64> {
+    meter
+        .f64_histogram("db.client.operation.duration")
+        .with_unit("s")
+        .with_description("Duration of database client operations")
+        .with_boundaries(vec![
+            0.0005, 0.001, 0.002, 0.004, 0.008, 0.016, 0.032, 0.064, 0.128, 0.256, 0.512, 1.024,
+            2.048, 4.096,
+        ])
+        .build()
+}
 
-    Ok(Providers { tracer, meter })
+/// Records the time since `start` on the `db.client.operation.duration` histogram.
+pub fn record_db_operation(
+    histogram: &Histogram<f64>,
+    collection: &'static str,
+    operation: &'static str,
+    start: Instant,
+) {
+    histogram.record(
+        start.elapsed().as_secs_f64(),
+        &[
+            KeyValue::new("db.operation", operation),
+            KeyValue::new("db.collection.name", collection),
+        ],
+    );
+}
+
+pub fn http_active_requests_counter(meter: &Meter) -> UpDownCounter<i64> {
+    meter
+        .i64_up_down_counter("http.server.active_requests")
+        .with_unit("{request}")
+        .with_description("Number of in-flight HTTP server requests")
+        .build()
+}
+
+pub fn health_db_check_duration_histogram(meter: &Meter) -> Histogram<f64> {
+    meter
+        .f64_histogram("app.health.db_check_duration")
+        .with_unit("s")
+        .with_description("Duration of the database check performed by the health endpoint")
+        .build()
+}
+
+pub fn avatar_size_histogram(meter: &Meter) -> Histogram<u64> {
+    meter
+        .u64_histogram("app.users.avatar.size")
+        .with_unit("By")
+        .with_description("Size of uploaded user avatars")
+        .with_boundaries(vec![
+            4096.0, 16384.0, 65536.0, 131072.0, 262144.0, 524288.0, 1048576.0,
+        ])
+        .build()
 }
@@ -0,0 +1,44 @@
+use anyhow::Context;
+use base64::{Engine, engine::general_purpose::URL_SAFE_NO_PAD};
+use uuid::Uuid;
+
+use crate::models::UserId;
+
+pub const DEFAULT_PAGE_LIMIT: u32 = 50;
+pub const DEFAULT_MAX_PAGE_SIZE: u32 = 200;
+
+/// The largest `limit` listing endpoints accept, from `MAX_PAGE_SIZE`, and what
+/// happens to a larger one, from `PAGE_SIZE_OVERFLOW`.
+#[derive(Clone, Copy)]
+pub struct PageSize {
+    pub max: u32,
+    /// Lower an oversized `limit` to `max` instead of rejecting it.
+    pub clamp: bool,
+}
+
+impl PageSize {
+    /// The limit a listing query runs with, or `None` when `limit` is zero or over
+    /// the maximum and must be rejected.
+    pub fn effective(self, limit: Option<u32>) -> Option<u32> {
+        match limit {
+            None => Some(DEFAULT_PAGE_LIMIT.min(self.max)),
+            Some(0) => None,
+            Some(limit) if limit <= self.max => Some(limit),
+            Some(_) if self.clamp => Some(self.max),
+            Some(_) => None,
+        }
+    }
+}
+
+pub fn encode_cursor(id: UserId) -> String {
+    URL_SAFE_NO_PAD.encode(id.as_uuid().as_bytes())
+}
+
+pub fn decode_cursor(cursor: &str) -> anyhow::Result<UserId> {
+    let bytes = URL_SAFE_NO_PAD
+        .decode(cursor)
+        .context("cursor is not valid base64")?;
+    Uuid::from_slice(&bytes)
+        .map(UserId::from)
+        .context("cursor does not encode a UUID")
+}
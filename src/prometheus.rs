@@ -0,0 +1,19 @@
+use axum::{
+    extract::State,
+    http::{StatusCode, header},
+    response::{IntoResponse, Response},
+};
+use prometheus::{Encoder, TextEncoder};
+
+use crate::state::AppState;
+
+const CONTENT_TYPE: &str = "text/plain; version=0.0.4";
+
+pub async fn metrics(State(state): State<AppState>) -> Response {
+    let mut body = Vec::new();
+    if let Err(err) = TextEncoder::new().encode(&state.prometheus.gather(), &mut body) {
+        return (StatusCode::INTERNAL_SERVER_ERROR, err.to_string()).into_response();
+    }
+
+    ([(header::CONTENT_TYPE, CONTENT_TYPE)], body).into_response()
+}
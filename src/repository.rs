@@ -0,0 +1,1054 @@
+mod addresses;
+mod audit;
+mod avatars;
+mod health;
+mod stats;
+
+use std::sync::Arc;
+
+use anyhow::Context;
+use async_trait::async_trait;
+use chrono::{DateTime, Utc};
+use futures_util::{StreamExt, TryStreamExt, stream::BoxStream};
+use sqlx::{FromRow, PgPool, QueryBuilder, Row};
+
+pub use addresses::{AddressRepository, PostgresAddressRepository};
+pub use audit::{AuditRepository, PostgresAuditRepository};
+pub use avatars::{AvatarRepository, PostgresAvatarRepository};
+pub use health::{DatabaseHealth, PostgresHealth};
+pub use stats::{PostgresStatsRepository, StatsRepository};
+
+use crate::db::{IDEMPOTENCY_KEY_TTL, begin_audited};
+use crate::models::{
+    CreateUserRequest, FirstName, LastName, MiddleName, SortField, SortOrder, User, UserId,
+};
+
+/// The columns [`User`] is read from by `FromRow`, for queries built at runtime that
+/// [`query_user!`] cannot check.
+macro_rules! user_columns {
+    () => {
+        "id, first_name, middle_name, last_name, email, created_at, updated_at, deleted_at, version"
+    };
+}
+
+/// `sqlx::query_as!` for a [`User`], checked against the schema at compile time. The
+/// user columns go between `$head` and `$tail`, with the overrides the macro needs: `id`
+/// and the names are read as their newtypes and `email` is a `CITEXT`, which sqlx reads
+/// as text.
+macro_rules! query_user {
+    ($head:tt, $tail:tt $(, $arg:expr)* $(,)?) => {
+        sqlx::query_as!(
+            $crate::models::User,
+            $head
+                + "id AS \"id: crate::models::UserId\", \
+                   first_name AS \"first_name: crate::models::FirstName\", \
+                   middle_name AS \"middle_name: crate::models::MiddleName\", \
+                   last_name AS \"last_name: crate::models::LastName\", email AS \"email: String\", \
+                   created_at, updated_at, deleted_at, version"
+                + $tail
+            $(, $arg)*
+        )
+    };
+}
+
+/// Which users `find_all` returns, already validated by the handler.
+pub struct UserQuery<'a> {
+    pub limit: u32,
+    pub offset: u32,
+    pub last_name: Option<&'a str>,
+    pub include_deleted: bool,
+    pub sort: SortField,
+    pub order: SortOrder,
+    /// Also count every matching user, not only the ones on the page.
+    pub with_count: bool,
+}
+
+pub struct UserPage {
+    pub users: Vec<User>,
+    /// Only known when counting was asked for and the page is not empty.
+    pub total_count: Option<i64>,
+}
+
+/// Live users with a given name, as found by `find_by_name`.
+pub struct NameMatches {
+    pub count: i64,
+    /// Oldest first, at most as many as were asked for.
+    pub ids: Vec<UserId>,
+}
+
+/// A step that must succeed for a new user to be kept, such as publishing a welcome
+/// event. `insert` runs it before committing, so a failure leaves nothing behind.
+#[async_trait]
+pub trait UserCreatedHook {
+    async fn user_created(&self, user: &User) -> anyhow::Result<()>;
+}
+
+pub type UserCreatedHooks = [Arc<dyn UserCreatedHook + Send + Sync>];
+
+/// Fields `update` may change; `None` leaves a field as it is.
+pub struct UserChanges<'a> {
+    pub first_name: Option<&'a FirstName>,
+    /// `Some(None)` clears the middle name.
+    pub middle_name: Option<Option<&'a MiddleName>>,
+    pub last_name: Option<&'a LastName>,
+    /// Only update when the stored version matches; `None` skips the check.
+    pub expected_version: Option<i32>,
+}
+
+pub enum UpdateOutcome {
+    Updated(User),
+    /// The user exists, but at a different version than the one expected.
+    VersionMismatch {
+        expected: i32,
+        actual: i32,
+    },
+    NotFound,
+}
+
+pub struct MergedUsers {
+    pub kept: User,
+    pub addresses_moved: u64,
+    pub audit_entries_moved: u64,
+}
+
+pub enum MergeOutcome {
+    Merged(MergedUsers),
+    /// This user is missing or deleted; nothing was changed.
+    NotFound(UserId),
+}
+
+/// Storage for users. Errors keep the underlying `sqlx::Error` reachable through
+/// downcasting, so `AppError` can still classify them.
+#[async_trait]
+pub trait UserRepository {
+    async fn find_all(&self, query: &UserQuery<'_>) -> anyhow::Result<UserPage>;
+
+    /// Up to `limit` users with an id greater than `after`, in id order.
+    async fn find_after(
+        &self,
+        after: Option<UserId>,
+        limit: u32,
+        last_name: Option<&str>,
+        include_deleted: bool,
+    ) -> anyhow::Result<Vec<User>>;
+
+    /// Every live user in id order, read as the caller consumes them.
+    fn stream_live(&self) -> BoxStream<'_, anyhow::Result<User>>;
+
+    /// Live users whose names match the full-text `query`, best match first.
+    async fn search(&self, query: &str, limit: u32, offset: u32) -> anyhow::Result<Vec<User>>;
+
+    async fn count_search(&self, query: &str) -> anyhow::Result<i64>;
+
+    /// Live users created at or after `since`, newest first.
+    async fn find_created_since(
+        &self,
+        since: DateTime<Utc>,
+        limit: u32,
+    ) -> anyhow::Result<Vec<User>>;
+
+    async fn find_by_id(&self, id: UserId, include_deleted: bool) -> anyhow::Result<Option<User>>;
+
+    /// The live users among `ids`, in no particular order.
+    async fn find_by_ids(&self, ids: &[UserId]) -> anyhow::Result<Vec<User>>;
+
+    /// Like `find_by_id`, without loading the row.
+    async fn exists(&self, id: UserId, include_deleted: bool) -> anyhow::Result<bool>;
+
+    /// Whether any user, soft-deleted ones included, has one of `emails` (compared
+    /// case-insensitively).
+    async fn email_exists(&self, emails: &[&str]) -> anyhow::Result<bool>;
+
+    async fn count(&self, last_name: Option<&str>, include_deleted: bool) -> anyhow::Result<i64>;
+
+    /// Live users whose first and last names match, ignoring case.
+    async fn find_by_name(
+        &self,
+        first_name: &str,
+        last_name: &str,
+        limit: u32,
+    ) -> anyhow::Result<NameMatches>;
+
+    /// The user created under `idempotency_key` as it was returned then, while the key
+    /// has not expired.
+    async fn find_by_idempotency_key(&self, key: &str) -> anyhow::Result<Option<User>>;
+
+    /// Inserts a user, first claiming `idempotency_key` when one is given, and runs
+    /// `hooks` in the same transaction. Returns `None` if the key is already held by
+    /// another live request, in which case nothing is written.
+    async fn insert(
+        &self,
+        id: UserId,
+        request: &CreateUserRequest,
+        idempotency_key: Option<&str>,
+        hooks: &UserCreatedHooks,
+    ) -> anyhow::Result<Option<User>>;
+
+    /// Inserts all the users in one statement and runs `hooks` for each of them in the
+    /// same transaction, so either all of them are created or none.
+    async fn insert_many(
+        &self,
+        users: &[(UserId, &CreateUserRequest)],
+        hooks: &UserCreatedHooks,
+    ) -> anyhow::Result<Vec<User>>;
+
+    async fn update(&self, id: UserId, changes: &UserChanges<'_>) -> anyhow::Result<UpdateOutcome>;
+
+    /// Soft-deletes a user; `false` when there was no live user to delete.
+    async fn delete(&self, id: UserId) -> anyhow::Result<bool>;
+
+    /// Soft-deletes the live users among `ids` and returns how many there were.
+    async fn delete_many(&self, ids: &[UserId]) -> anyhow::Result<u64>;
+
+    /// Clears `deleted_at` on a soft-deleted user; `None` when no deleted user has this id.
+    async fn restore(&self, id: UserId) -> anyhow::Result<Option<User>>;
+
+    /// Folds `remove` into `keep`: addresses, audit entries and, when `keep` has none,
+    /// the avatar move over, then `remove` is soft-deleted and a `merge` entry is added
+    /// to the audit trail of `keep`.
+    async fn merge(&self, keep: UserId, remove: UserId) -> anyhow::Result<MergeOutcome>;
+}
+
+pub struct PostgresUserRepository(pub PgPool);
+
+#[async_trait]
+impl UserRepository for PostgresUserRepository {
+    async fn find_all(&self, query: &UserQuery<'_>) -> anyhow::Result<UserPage> {
+        // The total comes back with the page through a window function.
+        let count_column = if query.with_count {
+            ", COUNT(*) OVER () AS total_count"
+        } else {
+            ""
+        };
+
+        // Only whitelisted column names and keywords are interpolated into the query.
+        let sql = format!(
+            concat!(
+                "SELECT ",
+                user_columns!(),
+                "{count_column} FROM users \
+                 WHERE ($3::text IS NULL OR last_name = $3) AND ($4 OR deleted_at IS NULL) \
+                 ORDER BY {column} {order}, id LIMIT $1 OFFSET $2"
+            ),
+            count_column = count_column,
+            column = query.sort.column(),
+            order = query.order.keyword(),
+        );
+        let rows = sqlx::query(&sql)
+            .bind(i64::from(query.limit))
+            .bind(i64::from(query.offset))
+            .bind(query.last_name)
+            .bind(query.include_deleted)
+            .fetch_all(&self.0)
+            .await
+            .context("Failed to fetch users")?;
+
+        let total_count = rows
+            .first()
+            .filter(|_| query.with_count)
+            .map(|row| row.try_get("total_count"))
+            .transpose()
+            .context("Failed to read user count")?;
+        Ok(UserPage {
+            users: rows
+                .iter()
+                .map(User::from_row)
+                .collect::<sqlx::Result<_>>()
+                .context("Failed to read users")?,
+            total_count,
+        })
+    }
+
+    async fn find_after(
+        &self,
+        after: Option<UserId>,
+        limit: u32,
+        last_name: Option<&str>,
+        include_deleted: bool,
+    ) -> anyhow::Result<Vec<User>> {
+        query_user!(
+            "SELECT ",
+            " FROM users \
+             WHERE ($1::uuid IS NULL OR id > $1) AND ($3::text IS NULL OR last_name = $3) \
+               AND ($4 OR deleted_at IS NULL) \
+             ORDER BY id LIMIT $2",
+            after as Option<UserId>,
+            i64::from(limit),
+            last_name,
+            include_deleted,
+        )
+        .fetch_all(&self.0)
+        .await
+        .context("Failed to fetch users page")
+    }
+
+    fn stream_live(&self) -> BoxStream<'_, anyhow::Result<User>> {
+        query_user!(
+            "SELECT ",
+            " FROM users WHERE deleted_at IS NULL ORDER BY id"
+        )
+        .fetch(&self.0)
+        .map_err(anyhow::Error::from)
+        .boxed()
+    }
+
+    async fn search(&self, query: &str, limit: u32, offset: u32) -> anyhow::Result<Vec<User>> {
+        // The tsvector expression must match users_name_fts_idx for the index to be used.
+        query_user!(
+            "SELECT ",
+            " FROM users \
+             WHERE to_tsvector('english', first_name || ' ' || last_name) @@ plainto_tsquery('english', $1) \
+               AND deleted_at IS NULL \
+             ORDER BY ts_rank(to_tsvector('english', first_name || ' ' || last_name), \
+                              plainto_tsquery('english', $1)) DESC, id \
+             LIMIT $2 OFFSET $3",
+            query,
+            i64::from(limit),
+            i64::from(offset),
+        )
+        .fetch_all(&self.0)
+        .await
+        .context("Failed to search users")
+    }
+
+    async fn count_search(&self, query: &str) -> anyhow::Result<i64> {
+        sqlx::query_scalar!(
+            r#"SELECT COUNT(*) AS "count!" FROM users
+               WHERE to_tsvector('english', first_name || ' ' || last_name) @@ plainto_tsquery('english', $1)
+                 AND deleted_at IS NULL"#,
+            query,
+        )
+        .fetch_one(&self.0)
+        .await
+        .context("Failed to count search results")
+    }
+
+    async fn find_created_since(
+        &self,
+        since: DateTime<Utc>,
+        limit: u32,
+    ) -> anyhow::Result<Vec<User>> {
+        query_user!(
+            "SELECT ",
+            " FROM users WHERE created_at >= $1 AND deleted_at IS NULL \
+             ORDER BY created_at DESC, id LIMIT $2",
+            since,
+            i64::from(limit),
+        )
+        .fetch_all(&self.0)
+        .await
+        .context("Failed to fetch recent users")
+    }
+
+    async fn find_by_id(&self, id: UserId, include_deleted: bool) -> anyhow::Result<Option<User>> {
+        query_user!(
+            "SELECT ",
+            " FROM users WHERE id = $1 AND ($2 OR deleted_at IS NULL)",
+            id as UserId,
+            include_deleted
+        )
+        .fetch_optional(&self.0)
+        .await
+        .context("Failed to fetch user")
+    }
+
+    async fn find_by_ids(&self, ids: &[UserId]) -> anyhow::Result<Vec<User>> {
+        query_user!(
+            "SELECT ",
+            " FROM users WHERE id = ANY($1) AND deleted_at IS NULL",
+            ids as &[UserId]
+        )
+        .fetch_all(&self.0)
+        .await
+        .context("Failed to look up users")
+    }
+
+    async fn exists(&self, id: UserId, include_deleted: bool) -> anyhow::Result<bool> {
+        sqlx::query_scalar!(
+            r#"SELECT EXISTS (SELECT 1 FROM users WHERE id = $1 AND ($2 OR deleted_at IS NULL)) AS "exists!""#,
+            id as UserId,
+            include_deleted,
+        )
+        .fetch_one(&self.0)
+        .await
+        .context("Failed to look up user")
+    }
+
+    async fn email_exists(&self, emails: &[&str]) -> anyhow::Result<bool> {
+        // email is CITEXT, so the comparison ignores case once the text parameters are cast.
+        sqlx::query_scalar!(
+            r#"SELECT EXISTS (SELECT 1 FROM users WHERE email = ANY($1::text[]::citext[])) AS "exists!""#,
+            emails as &[&str],
+        )
+        .fetch_one(&self.0)
+        .await
+        .context("Failed to look up email")
+    }
+
+    async fn count(&self, last_name: Option<&str>, include_deleted: bool) -> anyhow::Result<i64> {
+        sqlx::query_scalar!(
+            r#"SELECT COUNT(*) AS "count!" FROM users
+               WHERE ($1::text IS NULL OR last_name = $1) AND ($2 OR deleted_at IS NULL)"#,
+            last_name,
+            include_deleted,
+        )
+        .fetch_one(&self.0)
+        .await
+        .context("Failed to count users")
+    }
+
+    async fn find_by_name(
+        &self,
+        first_name: &str,
+        last_name: &str,
+        limit: u32,
+    ) -> anyhow::Result<NameMatches> {
+        // The window count is taken before LIMIT, so it covers every match.
+        let rows = sqlx::query!(
+            r#"SELECT id AS "id: UserId", COUNT(*) OVER () AS "count!" FROM users
+               WHERE lower(last_name) = lower($2) AND lower(first_name) = lower($1) AND deleted_at IS NULL
+               ORDER BY created_at, id
+               LIMIT $3"#,
+            first_name,
+            last_name,
+            i64::from(limit),
+        )
+        .fetch_all(&self.0)
+        .await
+        .context("Failed to look up users by name")?;
+        Ok(NameMatches {
+            count: rows.first().map_or(0, |row| row.count),
+            ids: rows.into_iter().map(|row| row.id).collect(),
+        })
+    }
+
+    async fn find_by_idempotency_key(&self, key: &str) -> anyhow::Result<Option<User>> {
+        let claimed = sqlx::query_scalar!(
+            "SELECT response_body FROM idempotency_keys \
+             WHERE key = $1 AND created_at > NOW() - make_interval(secs => $2)",
+            key,
+            IDEMPOTENCY_KEY_TTL.as_secs_f64(),
+        )
+        .fetch_optional(&self.0)
+        .await
+        .context("Failed to look up idempotency key")?;
+        match claimed {
+            None => return Ok(None),
+            Some(Some(body)) => {
+                return serde_json::from_value(body)
+                    .map(Some)
+                    .context("Failed to read stored idempotent response");
+            }
+            Some(None) => {}
+        }
+        // Keys claimed before response_body existed replay the current row.
+        query_user!(
+            "SELECT ",
+            " FROM users WHERE id = ( \
+               SELECT user_id FROM idempotency_keys \
+               WHERE key = $1 AND created_at > NOW() - make_interval(secs => $2))",
+            key,
+            IDEMPOTENCY_KEY_TTL.as_secs_f64(),
+        )
+        .fetch_optional(&self.0)
+        .await
+        .context("Failed to look up idempotency key")
+    }
+
+    async fn insert(
+        &self,
+        id: UserId,
+        request: &CreateUserRequest,
+        idempotency_key: Option<&str>,
+        hooks: &UserCreatedHooks,
+    ) -> anyhow::Result<Option<User>> {
+        let mut tx = begin_audited(&self.0)
+            .await
+            .context("Failed to insert user")?;
+        if let Some(key) = idempotency_key {
+            // Concurrent writers block on the primary key until the first one commits,
+            // then see the claim and back off. Expired keys are taken over.
+            let claimed = sqlx::query_scalar!(
+                "INSERT INTO idempotency_keys (key, user_id) VALUES ($1, $2) \
+                 ON CONFLICT (key) DO UPDATE SET user_id = EXCLUDED.user_id, created_at = NOW() \
+                 WHERE idempotency_keys.created_at <= NOW() - make_interval(secs => $3) \
+                 RETURNING key",
+                key,
+                id as UserId,
+                IDEMPOTENCY_KEY_TTL.as_secs_f64(),
+            )
+            .fetch_optional(&mut *tx)
+            .await
+            .context("Failed to claim idempotency key")?;
+            if claimed.is_none() {
+                return Ok(None);
+            }
+        }
+        let user = query_user!(
+            "INSERT INTO users (id, first_name, middle_name, last_name, email) \
+             VALUES ($1, $2, $3, $4, $5::text) RETURNING ",
+            "",
+            id as UserId,
+            &request.first_name as &FirstName,
+            request.middle_name.as_ref() as Option<&MiddleName>,
+            &request.last_name as &LastName,
+            request.email,
+        )
+        .fetch_one(&mut *tx)
+        .await
+        .context("Failed to insert user")?;
+        if let Some(key) = idempotency_key {
+            let body = serde_json::to_value(&user).context("Failed to serialize user")?;
+            sqlx::query!(
+                "UPDATE idempotency_keys SET response_body = $2 WHERE key = $1",
+                key,
+                body,
+            )
+            .execute(&mut *tx)
+            .await
+            .context("Failed to store idempotent response")?;
+        }
+        // Returning early drops the transaction, which rolls the insert back.
+        for hook in hooks {
+            hook.user_created(&user).await?;
+        }
+        tx.commit().await.context("Failed to insert user")?;
+        Ok(Some(user))
+    }
+
+    async fn insert_many(
+        &self,
+        users: &[(UserId, &CreateUserRequest)],
+        hooks: &UserCreatedHooks,
+    ) -> anyhow::Result<Vec<User>> {
+        if users.is_empty() {
+            return Ok(Vec::new());
+        }
+
+        let mut tx = begin_audited(&self.0)
+            .await
+            .context("Failed to insert users")?;
+        let mut query =
+            QueryBuilder::new("INSERT INTO users (id, first_name, middle_name, last_name, email) ");
+        query.push_values(users, |mut row, (id, request)| {
+            row.push_bind(*id)
+                .push_bind(&request.first_name)
+                .push_bind(&request.middle_name)
+                .push_bind(&request.last_name)
+                .push_bind(&request.email);
+        });
+        query.push(concat!(" RETURNING ", user_columns!()));
+        let users = query
+            .build_query_as()
+            .fetch_all(&mut *tx)
+            .await
+            .context("Failed to insert users")?;
+        for user in &users {
+            for hook in hooks {
+                hook.user_created(user).await?;
+            }
+        }
+        tx.commit().await.context("Failed to insert users")?;
+        Ok(users)
+    }
+
+    async fn update(&self, id: UserId, changes: &UserChanges<'_>) -> anyhow::Result<UpdateOutcome> {
+        let mut tx = begin_audited(&self.0)
+            .await
+            .context("Failed to update user")?;
+        // The row stays locked until commit, so the version compared is the one updated.
+        let actual = sqlx::query_scalar!(
+            "SELECT version FROM users WHERE id = $1 AND deleted_at IS NULL FOR UPDATE",
+            id as UserId
+        )
+        .fetch_optional(&mut *tx)
+        .await
+        .context("Failed to fetch user version")?;
+        let Some(actual) = actual else {
+            return Ok(UpdateOutcome::NotFound);
+        };
+        if let Some(expected) = changes.expected_version
+            && expected != actual
+        {
+            return Ok(UpdateOutcome::VersionMismatch { expected, actual });
+        }
+        let user = query_user!(
+            "UPDATE users SET first_name = COALESCE($3, first_name), \
+             middle_name = CASE WHEN $4 THEN $5::text ELSE middle_name END, \
+             last_name = COALESCE($6, last_name) \
+             WHERE id = $1 AND version = $2 RETURNING ",
+            "",
+            id as UserId,
+            actual,
+            changes.first_name as Option<&FirstName>,
+            changes.middle_name.is_some(),
+            changes.middle_name.flatten() as Option<&MiddleName>,
+            changes.last_name as Option<&LastName>,
+        )
+        .fetch_one(&mut *tx)
+        .await
+        .context("Failed to update user")?;
+        tx.commit().await.context("Failed to update user")?;
+        Ok(UpdateOutcome::Updated(user))
+    }
+
+    async fn delete(&self, id: UserId) -> anyhow::Result<bool> {
+        let mut tx = begin_audited(&self.0)
+            .await
+            .context("Failed to delete user")?;
+        let result = sqlx::query!(
+            "UPDATE users SET deleted_at = NOW() WHERE id = $1 AND deleted_at IS NULL",
+            id as UserId
+        )
+        .execute(&mut *tx)
+        .await
+        .context("Failed to delete user")?;
+        tx.commit().await.context("Failed to delete user")?;
+        Ok(result.rows_affected() > 0)
+    }
+
+    async fn delete_many(&self, ids: &[UserId]) -> anyhow::Result<u64> {
+        let mut tx = begin_audited(&self.0)
+            .await
+            .context("Failed to delete users")?;
+        let result = sqlx::query!(
+            "UPDATE users SET deleted_at = NOW() WHERE id = ANY($1) AND deleted_at IS NULL",
+            ids as &[UserId],
+        )
+        .execute(&mut *tx)
+        .await
+        .context("Failed to delete users")?;
+        tx.commit().await.context("Failed to delete users")?;
+        Ok(result.rows_affected())
+    }
+
+    async fn restore(&self, id: UserId) -> anyhow::Result<Option<User>> {
+        let mut tx = begin_audited(&self.0)
+            .await
+            .context("Failed to restore user")?;
+        let user = query_user!(
+            "UPDATE users SET deleted_at = NULL WHERE id = $1 AND deleted_at IS NOT NULL RETURNING ",
+            "",
+            id as UserId,
+        )
+        .fetch_optional(&mut *tx)
+        .await
+        .context("Failed to restore user")?;
+        tx.commit().await.context("Failed to restore user")?;
+        Ok(user)
+    }
+
+    async fn merge(&self, keep: UserId, remove: UserId) -> anyhow::Result<MergeOutcome> {
+        let mut tx = begin_audited(&self.0)
+            .await
+            .context("Failed to merge users")?;
+        // Both rows are locked in id order so concurrent merges cannot deadlock.
+        let users = query_user!(
+            "SELECT ",
+            " FROM users WHERE id = ANY($1) AND deleted_at IS NULL ORDER BY id FOR UPDATE",
+            &[keep, remove][..] as &[UserId],
+        )
+        .fetch_all(&mut *tx)
+        .await
+        .context("Failed to lock users")?;
+        if !users.iter().any(|user| user.id == remove) {
+            return Ok(MergeOutcome::NotFound(remove));
+        }
+        let Some(kept) = users.into_iter().find(|user| user.id == keep) else {
+            return Ok(MergeOutcome::NotFound(keep));
+        };
+
+        let addresses = sqlx::query!(
+            "UPDATE addresses SET user_id = $1 WHERE user_id = $2",
+            keep as UserId,
+            remove as UserId
+        )
+        .execute(&mut *tx)
+        .await
+        .context("Failed to move addresses")?;
+        let audit_entries = sqlx::query!(
+            "UPDATE user_audit SET user_id = $1 WHERE user_id = $2",
+            keep as UserId,
+            remove as UserId
+        )
+        .execute(&mut *tx)
+        .await
+        .context("Failed to move audit entries")?;
+        sqlx::query!(
+            "UPDATE user_avatars SET user_id = $1 WHERE user_id = $2 \
+               AND NOT EXISTS (SELECT 1 FROM user_avatars WHERE user_id = $1)",
+            keep as UserId,
+            remove as UserId,
+        )
+        .execute(&mut *tx)
+        .await
+        .context("Failed to move avatar")?;
+        // The trigger records the soft delete under the removed id.
+        sqlx::query!(
+            "UPDATE users SET deleted_at = NOW() WHERE id = $1",
+            remove as UserId
+        )
+        .execute(&mut *tx)
+        .await
+        .context("Failed to delete merged user")?;
+        sqlx::query!(
+            "INSERT INTO user_audit (user_id, operation, old_values, new_values, trace_id) \
+             SELECT $1, 'merge', to_jsonb(users), jsonb_build_object('merged_from', $2::uuid), \
+                    NULLIF(current_setting('app.trace_id', true), '') \
+             FROM users WHERE id = $2",
+            keep as UserId,
+            remove as UserId,
+        )
+        .execute(&mut *tx)
+        .await
+        .context("Failed to record merge")?;
+        tx.commit().await.context("Failed to merge users")?;
+        Ok(MergeOutcome::Merged(MergedUsers {
+            kept,
+            addresses_moved: addresses.rows_affected(),
+            audit_entries_moved: audit_entries.rows_affected(),
+        }))
+    }
+}
+
+/// Map-backed repository for handler tests. Idempotency keys never expire.
+#[cfg(test)]
+#[derive(Default)]
+pub struct InMemoryUserRepository {
+    users: tokio::sync::RwLock<std::collections::HashMap<UserId, User>>,
+    /// The user each key created, as it was inserted.
+    idempotency_keys: tokio::sync::Mutex<std::collections::HashMap<String, User>>,
+}
+
+#[cfg(test)]
+impl InMemoryUserRepository {
+    pub fn with_users(users: impl IntoIterator<Item = User>) -> Self {
+        Self {
+            users: tokio::sync::RwLock::new(
+                users.into_iter().map(|user| (user.id, user)).collect(),
+            ),
+            idempotency_keys: Default::default(),
+        }
+    }
+
+    async fn matching_search(&self, query: &str) -> Vec<User> {
+        let terms: Vec<String> = query.split_whitespace().map(str::to_lowercase).collect();
+        let users = self.users.read().await;
+        users
+            .values()
+            .filter(|user| user.deleted_at.is_none())
+            .filter(|user| {
+                let names = [
+                    user.first_name.to_lowercase(),
+                    user.last_name.to_lowercase(),
+                ];
+                terms.iter().all(|term| names.contains(term))
+            })
+            .cloned()
+            .collect()
+    }
+}
+
+#[cfg(test)]
+fn new_user(id: UserId, request: &CreateUserRequest) -> User {
+    let now = chrono::Utc::now();
+    User {
+        id,
+        first_name: request.first_name.clone(),
+        middle_name: request.middle_name.clone(),
+        last_name: request.last_name.clone(),
+        email: request.email.clone(),
+        created_at: now,
+        updated_at: now,
+        deleted_at: None,
+        version: 1,
+    }
+}
+
+#[cfg(test)]
+#[async_trait]
+impl UserRepository for InMemoryUserRepository {
+    async fn find_all(&self, query: &UserQuery<'_>) -> anyhow::Result<UserPage> {
+        let users = self.users.read().await;
+        let mut matching: Vec<&User> = users
+            .values()
+            .filter(|user| {
+                query
+                    .last_name
+                    .is_none_or(|last_name| user.last_name == last_name)
+            })
+            .filter(|user| query.include_deleted || user.deleted_at.is_none())
+            .collect();
+        matching.sort_by(|a, b| {
+            let by_column = match query.sort {
+                SortField::Id => a.id.cmp(&b.id),
+                SortField::FirstName => a.first_name.cmp(&b.first_name),
+                SortField::LastName => a.last_name.cmp(&b.last_name),
+            };
+            let by_column = match query.order {
+                SortOrder::Asc => by_column,
+                SortOrder::Desc => by_column.reverse(),
+            };
+            by_column.then(a.id.cmp(&b.id))
+        });
+        let total = matching.len() as i64;
+        let page: Vec<User> = matching
+            .into_iter()
+            .skip(query.offset as usize)
+            .take(query.limit as usize)
+            .cloned()
+            .collect();
+        Ok(UserPage {
+            total_count: (query.with_count && !page.is_empty()).then_some(total),
+            users: page,
+        })
+    }
+
+    async fn find_after(
+        &self,
+        after: Option<UserId>,
+        limit: u32,
+        last_name: Option<&str>,
+        include_deleted: bool,
+    ) -> anyhow::Result<Vec<User>> {
+        let users = self.users.read().await;
+        let mut matching: Vec<User> = users
+            .values()
+            .filter(|user| after.is_none_or(|after| user.id > after))
+            .filter(|user| last_name.is_none_or(|last_name| user.last_name == last_name))
+            .filter(|user| include_deleted || user.deleted_at.is_none())
+            .cloned()
+            .collect();
+        matching.sort_by_key(|user| user.id);
+        matching.truncate(limit as usize);
+        Ok(matching)
+    }
+
+    fn stream_live(&self) -> BoxStream<'_, anyhow::Result<User>> {
+        futures_util::stream::once(async {
+            let users = self.users.read().await;
+            let mut live: Vec<User> = users
+                .values()
+                .filter(|user| user.deleted_at.is_none())
+                .cloned()
+                .collect();
+            live.sort_by_key(|user| user.id);
+            futures_util::stream::iter(live.into_iter().map(Ok))
+        })
+        .flatten()
+        .boxed()
+    }
+
+    /// Matches whole words of either name, ignoring case, in id order.
+    async fn search(&self, query: &str, limit: u32, offset: u32) -> anyhow::Result<Vec<User>> {
+        let mut matching = self.matching_search(query).await;
+        matching.sort_by_key(|user| user.id);
+        Ok(matching
+            .into_iter()
+            .skip(offset as usize)
+            .take(limit as usize)
+            .collect())
+    }
+
+    async fn count_search(&self, query: &str) -> anyhow::Result<i64> {
+        Ok(self.matching_search(query).await.len() as i64)
+    }
+
+    async fn find_created_since(
+        &self,
+        since: DateTime<Utc>,
+        limit: u32,
+    ) -> anyhow::Result<Vec<User>> {
+        let users = self.users.read().await;
+        let mut matching: Vec<User> = users
+            .values()
+            .filter(|user| user.created_at >= since && user.deleted_at.is_none())
+            .cloned()
+            .collect();
+        matching.sort_by(|a, b| b.created_at.cmp(&a.created_at).then(a.id.cmp(&b.id)));
+        matching.truncate(limit as usize);
+        Ok(matching)
+    }
+
+    async fn find_by_id(&self, id: UserId, include_deleted: bool) -> anyhow::Result<Option<User>> {
+        let users = self.users.read().await;
+        Ok(users
+            .get(&id)
+            .filter(|user| include_deleted || user.deleted_at.is_none())
+            .cloned())
+    }
+
+    async fn find_by_ids(&self, ids: &[UserId]) -> anyhow::Result<Vec<User>> {
+        let users = self.users.read().await;
+        Ok(ids
+            .iter()
+            .filter_map(|id| users.get(id))
+            .filter(|user| user.deleted_at.is_none())
+            .cloned()
+            .collect())
+    }
+
+    async fn exists(&self, id: UserId, include_deleted: bool) -> anyhow::Result<bool> {
+        Ok(self.find_by_id(id, include_deleted).await?.is_some())
+    }
+
+    async fn email_exists(&self, emails: &[&str]) -> anyhow::Result<bool> {
+        let users = self.users.read().await;
+        Ok(users.values().any(|user| {
+            emails
+                .iter()
+                .any(|email| user.email.to_lowercase() == email.to_lowercase())
+        }))
+    }
+
+    async fn count(&self, last_name: Option<&str>, include_deleted: bool) -> anyhow::Result<i64> {
+        let users = self.users.read().await;
+        Ok(users
+            .values()
+            .filter(|user| last_name.is_none_or(|last_name| user.last_name == last_name))
+            .filter(|user| include_deleted || user.deleted_at.is_none())
+            .count() as i64)
+    }
+
+    async fn find_by_name(
+        &self,
+        first_name: &str,
+        last_name: &str,
+        limit: u32,
+    ) -> anyhow::Result<NameMatches> {
+        let users = self.users.read().await;
+        let mut matching: Vec<&User> = users
+            .values()
+            .filter(|user| user.first_name.to_lowercase() == first_name.to_lowercase())
+            .filter(|user| user.last_name.to_lowercase() == last_name.to_lowercase())
+            .filter(|user| user.deleted_at.is_none())
+            .collect();
+        matching.sort_by(|a, b| a.created_at.cmp(&b.created_at).then(a.id.cmp(&b.id)));
+        Ok(NameMatches {
+            count: matching.len() as i64,
+            ids: matching
+                .iter()
+                .take(limit as usize)
+                .map(|user| user.id)
+                .collect(),
+        })
+    }
+
+    async fn find_by_idempotency_key(&self, key: &str) -> anyhow::Result<Option<User>> {
+        Ok(self.idempotency_keys.lock().await.get(key).cloned())
+    }
+
+    async fn insert(
+        &self,
+        id: UserId,
+        request: &CreateUserRequest,
+        idempotency_key: Option<&str>,
+        hooks: &UserCreatedHooks,
+    ) -> anyhow::Result<Option<User>> {
+        // Held until the user is stored, like the claimed row in Postgres.
+        let mut keys = self.idempotency_keys.lock().await;
+        if idempotency_key.is_some_and(|key| keys.contains_key(key)) {
+            return Ok(None);
+        }
+        let user = new_user(id, request);
+        for hook in hooks {
+            hook.user_created(&user).await?;
+        }
+        self.users.write().await.insert(id, user.clone());
+        if let Some(key) = idempotency_key {
+            keys.insert(key.to_owned(), user.clone());
+        }
+        Ok(Some(user))
+    }
+
+    async fn insert_many(
+        &self,
+        new_users: &[(UserId, &CreateUserRequest)],
+        hooks: &UserCreatedHooks,
+    ) -> anyhow::Result<Vec<User>> {
+        let created: Vec<User> = new_users
+            .iter()
+            .map(|(id, request)| new_user(*id, request))
+            .collect();
+        for user in &created {
+            for hook in hooks {
+                hook.user_created(user).await?;
+            }
+        }
+        let mut users = self.users.write().await;
+        users.extend(created.iter().map(|user| (user.id, user.clone())));
+        Ok(created)
+    }
+
+    async fn update(&self, id: UserId, changes: &UserChanges<'_>) -> anyhow::Result<UpdateOutcome> {
+        let mut users = self.users.write().await;
+        let Some(user) = users.get_mut(&id).filter(|user| user.deleted_at.is_none()) else {
+            return Ok(UpdateOutcome::NotFound);
+        };
+        if let Some(expected) = changes.expected_version
+            && expected != user.version
+        {
+            return Ok(UpdateOutcome::VersionMismatch {
+                expected,
+                actual: user.version,
+            });
+        }
+        if let Some(first_name) = changes.first_name {
+            user.first_name = first_name.to_owned();
+        }
+        if let Some(middle_name) = changes.middle_name {
+            user.middle_name = middle_name.cloned();
+        }
+        if let Some(last_name) = changes.last_name {
+            user.last_name = last_name.to_owned();
+        }
+        user.version += 1;
+        user.updated_at = chrono::Utc::now();
+        Ok(UpdateOutcome::Updated(user.clone()))
+    }
+
+    async fn delete(&self, id: UserId) -> anyhow::Result<bool> {
+        let mut users = self.users.write().await;
+        let Some(user) = users.get_mut(&id).filter(|user| user.deleted_at.is_none()) else {
+            return Ok(false);
+        };
+        user.deleted_at = Some(chrono::Utc::now());
+        user.version += 1;
+        Ok(true)
+    }
+
+    async fn delete_many(&self, ids: &[UserId]) -> anyhow::Result<u64> {
+        let mut deleted = 0;
+        for id in ids {
+            if self.delete(*id).await? {
+                deleted += 1;
+            }
+        }
+        Ok(deleted)
+    }
+
+    async fn restore(&self, id: UserId) -> anyhow::Result<Option<User>> {
+        let mut users = self.users.write().await;
+        let Some(user) = users.get_mut(&id).filter(|user| user.deleted_at.is_some()) else {
+            return Ok(None);
+        };
+        user.deleted_at = None;
+        user.version += 1;
+        Ok(Some(user.clone()))
+    }
+
+    /// Only the users are tracked, so nothing else moves.
+    async fn merge(&self, keep: UserId, remove: UserId) -> anyhow::Result<MergeOutcome> {
+        let Some(kept) = self.find_by_id(keep, false).await? else {
+            return Ok(MergeOutcome::NotFound(keep));
+        };
+        if !self.delete(remove).await? {
+            return Ok(MergeOutcome::NotFound(remove));
+        }
+        Ok(MergeOutcome::Merged(MergedUsers {
+            kept,
+            addresses_moved: 0,
+            audit_entries_moved: 0,
+        }))
+    }
+}
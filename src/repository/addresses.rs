@@ -0,0 +1,94 @@
+use anyhow::Context;
+use async_trait::async_trait;
+use sqlx::PgPool;
+use uuid::Uuid;
+
+use crate::models::{Address, CreateAddressRequest, UserId};
+
+/// `sqlx::query_as!` for an [`Address`], with the address columns after `$head` and
+/// `user_id` read as a [`UserId`].
+macro_rules! query_address {
+    ($head:tt, $tail:tt $(, $arg:expr)* $(,)?) => {
+        sqlx::query_as!(
+            Address,
+            $head
+                + "id, user_id AS \"user_id: UserId\", line1, line2, city, postal_code, country, \
+                   created_at"
+                + $tail
+            $(, $arg)*
+        )
+    };
+}
+
+/// Storage for the postal addresses of users.
+#[async_trait]
+pub trait AddressRepository {
+    /// Addresses of `user_id`, oldest first. Does not check that the user exists.
+    async fn find_by_user(&self, user_id: UserId) -> anyhow::Result<Vec<Address>>;
+
+    /// Adds an address to a live user; `None` when there is no such user.
+    async fn insert(
+        &self,
+        id: Uuid,
+        user_id: UserId,
+        request: &CreateAddressRequest,
+    ) -> anyhow::Result<Option<Address>>;
+
+    /// `false` when the live user has no address with this id.
+    async fn delete(&self, user_id: UserId, id: Uuid) -> anyhow::Result<bool>;
+}
+
+pub struct PostgresAddressRepository(pub PgPool);
+
+#[async_trait]
+impl AddressRepository for PostgresAddressRepository {
+    async fn find_by_user(&self, user_id: UserId) -> anyhow::Result<Vec<Address>> {
+        query_address!(
+            "SELECT ",
+            " FROM addresses WHERE user_id = $1 ORDER BY created_at, id",
+            user_id as UserId,
+        )
+        .fetch_all(&self.0)
+        .await
+        .context("Failed to fetch addresses")
+    }
+
+    async fn insert(
+        &self,
+        id: Uuid,
+        user_id: UserId,
+        request: &CreateAddressRequest,
+    ) -> anyhow::Result<Option<Address>> {
+        // Selecting from users inserts nothing for a missing or deleted user, which
+        // avoids surfacing the foreign key violation as a conflict.
+        query_address!(
+            "INSERT INTO addresses (id, user_id, line1, line2, city, postal_code, country) \
+             SELECT $1, id, $3, $4, $5, $6, $7 FROM users WHERE id = $2 AND deleted_at IS NULL \
+             RETURNING ",
+            "",
+            id,
+            user_id as UserId,
+            request.line1,
+            request.line2,
+            request.city,
+            request.postal_code,
+            request.country,
+        )
+        .fetch_optional(&self.0)
+        .await
+        .context("Failed to insert address")
+    }
+
+    async fn delete(&self, user_id: UserId, id: Uuid) -> anyhow::Result<bool> {
+        let result = sqlx::query!(
+            "DELETE FROM addresses WHERE id = $2 AND user_id = $1 \
+               AND user_id IN (SELECT id FROM users WHERE deleted_at IS NULL)",
+            user_id as UserId,
+            id,
+        )
+        .execute(&self.0)
+        .await
+        .context("Failed to delete address")?;
+        Ok(result.rows_affected() > 0)
+    }
+}
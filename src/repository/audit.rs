@@ -0,0 +1,78 @@
+use anyhow::Context;
+use async_trait::async_trait;
+use sqlx::PgPool;
+
+use crate::models::{AuditEntry, UserId};
+
+pub struct AuditPage {
+    pub entries: Vec<AuditEntry>,
+    /// Only known when the page is not empty.
+    pub total_count: Option<i64>,
+}
+
+/// Read access to `user_audit`, which the database triggers write.
+#[async_trait]
+pub trait AuditRepository {
+    /// A page of the entries of `user_id`, newest first.
+    async fn find_by_user(
+        &self,
+        user_id: UserId,
+        limit: u32,
+        offset: u32,
+    ) -> anyhow::Result<AuditPage>;
+
+    async fn count_by_user(&self, user_id: UserId) -> anyhow::Result<i64>;
+}
+
+pub struct PostgresAuditRepository(pub PgPool);
+
+#[async_trait]
+impl AuditRepository for PostgresAuditRepository {
+    async fn find_by_user(
+        &self,
+        user_id: UserId,
+        limit: u32,
+        offset: u32,
+    ) -> anyhow::Result<AuditPage> {
+        let rows = sqlx::query!(
+            r#"SELECT id, user_id AS "user_id: UserId", operation, old_values, new_values,
+                      trace_id, created_at, COUNT(*) OVER () AS "total_count!"
+               FROM user_audit WHERE user_id = $1
+               ORDER BY id DESC
+               LIMIT $2 OFFSET $3"#,
+            user_id as UserId,
+            i64::from(limit),
+            i64::from(offset),
+        )
+        .fetch_all(&self.0)
+        .await
+        .context("Failed to fetch audit entries")?;
+
+        let _span = tracing::info_span!("result.map", row_count = rows.len()).entered();
+        Ok(AuditPage {
+            total_count: rows.first().map(|row| row.total_count),
+            entries: rows
+                .into_iter()
+                .map(|row| AuditEntry {
+                    id: row.id,
+                    user_id: row.user_id,
+                    operation: row.operation,
+                    old_values: row.old_values,
+                    new_values: row.new_values,
+                    trace_id: row.trace_id,
+                    created_at: row.created_at,
+                })
+                .collect(),
+        })
+    }
+
+    async fn count_by_user(&self, user_id: UserId) -> anyhow::Result<i64> {
+        sqlx::query_scalar!(
+            r#"SELECT COUNT(*) AS "count!" FROM user_audit WHERE user_id = $1"#,
+            user_id as UserId,
+        )
+        .fetch_one(&self.0)
+        .await
+        .context("Failed to count audit entries")
+    }
+}
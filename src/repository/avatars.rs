@@ -0,0 +1,70 @@
+use anyhow::Context;
+use async_trait::async_trait;
+use chrono::{DateTime, Utc};
+use sqlx::PgPool;
+
+use crate::models::UserId;
+
+pub struct Avatar {
+    pub content_type: String,
+    pub data: Vec<u8>,
+    /// Hex SHA-256 of `data`.
+    pub digest: String,
+    pub updated_at: DateTime<Utc>,
+}
+
+/// Storage for the one avatar image each user may have.
+#[async_trait]
+pub trait AvatarRepository {
+    /// Stores or replaces the avatar of a live user and returns its digest; `None`
+    /// when there is no such user.
+    async fn upsert(
+        &self,
+        user_id: UserId,
+        content_type: &str,
+        data: &[u8],
+    ) -> anyhow::Result<Option<String>>;
+
+    /// The avatar of a live user, if it has one.
+    async fn find_by_user(&self, user_id: UserId) -> anyhow::Result<Option<Avatar>>;
+}
+
+pub struct PostgresAvatarRepository(pub PgPool);
+
+#[async_trait]
+impl AvatarRepository for PostgresAvatarRepository {
+    async fn upsert(
+        &self,
+        user_id: UserId,
+        content_type: &str,
+        data: &[u8],
+    ) -> anyhow::Result<Option<String>> {
+        // Selecting from users writes nothing for a missing or deleted user, as for addresses.
+        sqlx::query_scalar!(
+            "INSERT INTO user_avatars (user_id, content_type, data, digest) \
+             SELECT id, $2, $3, encode(sha256($3), 'hex') FROM users WHERE id = $1 AND deleted_at IS NULL \
+             ON CONFLICT (user_id) DO UPDATE \
+             SET content_type = EXCLUDED.content_type, data = EXCLUDED.data, \
+                 digest = EXCLUDED.digest, updated_at = NOW() \
+             RETURNING digest",
+            user_id as UserId,
+            content_type,
+            data,
+        )
+        .fetch_optional(&self.0)
+        .await
+        .context("Failed to store avatar")
+    }
+
+    async fn find_by_user(&self, user_id: UserId) -> anyhow::Result<Option<Avatar>> {
+        sqlx::query_as!(
+            Avatar,
+            "SELECT a.content_type, a.data, a.digest, a.updated_at FROM user_avatars a \
+             JOIN users u ON u.id = a.user_id WHERE a.user_id = $1 AND u.deleted_at IS NULL",
+            user_id as UserId,
+        )
+        .fetch_optional(&self.0)
+        .await
+        .context("Failed to fetch avatar")
+    }
+}
@@ -0,0 +1,33 @@
+use async_trait::async_trait;
+use sqlx::PgPool;
+
+/// The database checks behind `/health` and `/readyz`. Errors are reported to the
+/// prober as they are, so no context is added.
+#[async_trait]
+pub trait DatabaseHealth {
+    /// Runs a trivial query.
+    async fn ping(&self) -> anyhow::Result<()>;
+
+    /// `false` when a migration is marked as failed.
+    async fn migrations_succeeded(&self) -> anyhow::Result<bool>;
+}
+
+pub struct PostgresHealth(pub PgPool);
+
+#[async_trait]
+impl DatabaseHealth for PostgresHealth {
+    async fn ping(&self) -> anyhow::Result<()> {
+        sqlx::query_scalar!(r#"SELECT 1 AS "one!""#)
+            .fetch_one(&self.0)
+            .await?;
+        Ok(())
+    }
+
+    async fn migrations_succeeded(&self) -> anyhow::Result<bool> {
+        Ok(sqlx::query_scalar!(
+            r#"SELECT NOT EXISTS (SELECT 1 FROM _sqlx_migrations WHERE NOT success) AS "succeeded!""#
+        )
+        .fetch_one(&self.0)
+        .await?)
+    }
+}
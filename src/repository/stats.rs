@@ -0,0 +1,55 @@
+use anyhow::Context;
+use async_trait::async_trait;
+use sqlx::PgPool;
+
+use crate::models::{DailyCount, LastNameCount};
+
+/// Aggregates over live users for `GET /users/stats`.
+#[async_trait]
+pub trait StatsRepository {
+    /// Signups on each of the last `days` UTC days, oldest first, including days
+    /// without any.
+    async fn created_per_day(&self, days: i32) -> anyhow::Result<Vec<DailyCount>>;
+
+    /// The `limit` most common last names, most common first.
+    async fn top_last_names(&self, limit: i64) -> anyhow::Result<Vec<LastNameCount>>;
+}
+
+pub struct PostgresStatsRepository(pub PgPool);
+
+#[async_trait]
+impl StatsRepository for PostgresStatsRepository {
+    async fn created_per_day(&self, days: i32) -> anyhow::Result<Vec<DailyCount>> {
+        // Days come from generate_series so that days without signups still appear, and the
+        // range join keeps the created_at index usable.
+        sqlx::query_as!(
+            DailyCount,
+            r#"SELECT to_char(day, 'YYYY-MM-DD') AS "date!", COUNT(u.id) AS "count!"
+               FROM generate_series(
+                   date_trunc('day', NOW() AT TIME ZONE 'UTC') - make_interval(days => $1 - 1),
+                   date_trunc('day', NOW() AT TIME ZONE 'UTC'),
+                   INTERVAL '1 day'
+               ) AS day
+               LEFT JOIN users u ON u.deleted_at IS NULL
+                   AND u.created_at >= day AT TIME ZONE 'UTC'
+                   AND u.created_at < (day + INTERVAL '1 day') AT TIME ZONE 'UTC'
+               GROUP BY day ORDER BY day"#,
+            days,
+        )
+        .fetch_all(&self.0)
+        .await
+        .context("Failed to count users per day")
+    }
+
+    async fn top_last_names(&self, limit: i64) -> anyhow::Result<Vec<LastNameCount>> {
+        sqlx::query_as!(
+            LastNameCount,
+            r#"SELECT last_name, COUNT(*) AS "count!" FROM users WHERE deleted_at IS NULL
+               GROUP BY last_name ORDER BY COUNT(*) DESC, last_name LIMIT $1"#,
+            limit,
+        )
+        .fetch_all(&self.0)
+        .await
+        .context("Failed to count users per last name")
+    }
+}
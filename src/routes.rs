@@ -1,15 +1,218 @@
-use axum::{Router, routing::{get, post}};
+use std::time::Duration;
+
+use anyhow::Context;
+use axum::{
+    Extension, Router,
+    error_handling::HandleErrorLayer,
+    extract::DefaultBodyLimit,
+    http::{HeaderName, HeaderValue, Method, header},
+    middleware::from_fn_with_state,
+    routing::{delete, get, post, put},
+};
 use axum_tracing_opentelemetry::middleware::{OtelAxumLayer, OtelInResponseLayer};
+use tower::{ServiceBuilder, timeout::TimeoutLayer};
+use tower_http::{
+    compression::CompressionLayer,
+    cors::{AllowOrigin, CorsLayer},
+};
 
-use crate::handlers::{add_user, get_user, get_users};
+use crate::handlers::{
+    MAX_AVATAR_BYTES, MAX_LOOKUP_IDS, add_address, add_user, add_users, count_users,
+    delete_address, delete_user, delete_users, export_users, export_users_csv, get_addresses,
+    get_avatar, get_recent_users, get_user, get_user_audit, get_user_history, get_user_stats,
+    get_users, get_users_page, head_user, lookup_users, merge_users, patch_user, put_avatar,
+    restore_user, search_users, update_user, users_exist,
+};
+use crate::health::{health_check, livez, readyz};
+use crate::middleware::{
+    BaggageLayer, RequestIdLayer, advertise_max_page_size, handle_timeout_error,
+    record_request_duration, require_write_auth, track_active_requests,
+};
+use crate::models::BodyLimit;
 use crate::state::AppState;
 
-pub fn create_router(state: AppState) -> Router {
-    Router::new()
-        .route("/user/{id}", get(get_user))
+/// Prefix the API routes are mounted under; empty while they are served from the root.
+pub const BASE_PATH: &str = "";
+
+const BULK_BODY_BYTES_PER_ITEM: usize = 512;
+// A quoted UUID is 38 bytes; the rest leaves room for separators and whitespace, so
+// too many ids get the descriptive 400 rather than a 413.
+const LOOKUP_BODY_BYTES_PER_ID: usize = 64;
+
+/// Origins allowed to call the API from a browser, from `CORS_ALLOWED_ORIGINS`.
+pub enum CorsConfig {
+    AnyOrigin,
+    Origins(Vec<HeaderValue>),
+}
+
+impl CorsConfig {
+    /// Parses a comma-separated origin list; `*` (the default) allows any origin.
+    pub fn parse(value: &str) -> anyhow::Result<Self> {
+        if value.trim() == "*" {
+            return Ok(Self::AnyOrigin);
+        }
+        let origins = value
+            .split(',')
+            .map(str::trim)
+            .filter(|origin| !origin.is_empty())
+            .map(|origin| {
+                HeaderValue::from_str(origin)
+                    .with_context(|| format!("Invalid CORS origin {origin:?}"))
+            })
+            .collect::<anyhow::Result<Vec<_>>>()?;
+        anyhow::ensure!(
+            !origins.is_empty(),
+            "CORS_ALLOWED_ORIGINS must list at least one origin"
+        );
+        Ok(Self::Origins(origins))
+    }
+
+    fn layer(self) -> CorsLayer {
+        let allow_origin = match self {
+            Self::AnyOrigin => AllowOrigin::any(),
+            Self::Origins(origins) => AllowOrigin::list(origins),
+        };
+        CorsLayer::new()
+            .allow_origin(allow_origin)
+            .allow_methods([
+                Method::GET,
+                Method::POST,
+                Method::PUT,
+                Method::PATCH,
+                Method::DELETE,
+            ])
+            .allow_headers([
+                header::AUTHORIZATION,
+                header::CONTENT_TYPE,
+                header::IF_MATCH,
+                header::IF_NONE_MATCH,
+                HeaderName::from_static("prefer"),
+                HeaderName::from_static("idempotency-key"),
+                HeaderName::from_static("x-admin-key"),
+                HeaderName::from_static("x-api-key"),
+                HeaderName::from_static("x-request-id"),
+                HeaderName::from_static("traceparent"),
+                HeaderName::from_static("baggage"),
+                HeaderName::from_static("b3"),
+            ])
+            .expose_headers([
+                header::ETAG,
+                header::LAST_MODIFIED,
+                header::LOCATION,
+                header::RETRY_AFTER,
+                HeaderName::from_static("x-request-id"),
+                HeaderName::from_static("x-total-count"),
+                HeaderName::from_static("x-max-page-size"),
+                HeaderName::from_static("preference-applied"),
+                HeaderName::from_static("traceparent"),
+            ])
+    }
+}
+
+fn with_body_limit(router: Router<AppState>, limit: usize) -> Router<AppState> {
+    router
+        .layer(DefaultBodyLimit::max(limit))
+        .layer(Extension(BodyLimit(limit)))
+}
+
+pub fn create_router(
+    state: AppState,
+    cors: CorsConfig,
+    compression_enabled: bool,
+    request_timeout: Duration,
+) -> Router {
+    // route_layer only wraps matched routes, so unknown paths still 404 without a key.
+    let single_writes = Router::new()
+        .route(
+            "/user/{id}",
+            put(update_user).patch(patch_user).delete(delete_user),
+        )
+        .route("/user/{id}/restore", post(restore_user))
+        .route("/user/{id}/addresses", post(add_address))
+        .route("/user/{id}/addresses/{address_id}", delete(delete_address))
+        .route("/users/merge", post(merge_users))
+        .route("/user", post(add_user));
+    let bulk_writes = Router::new()
+        .route("/users", post(add_users))
+        .route("/users/bulk", post(add_users))
+        .route("/users/delete", post(delete_users));
+    let avatar_writes = Router::new().route("/user/{id}/avatar", put(put_avatar));
+    // Bulk bodies may carry up to MAX_BULK_USERS items, so their limit scales with it.
+    let bulk_body_bytes = state.max_body_bytes.max(
+        state
+            .max_bulk_users
+            .saturating_mul(BULK_BODY_BYTES_PER_ITEM),
+    );
+    let writes = with_body_limit(single_writes, state.max_body_bytes)
+        .merge(with_body_limit(bulk_writes, bulk_body_bytes))
+        .merge(with_body_limit(avatar_writes, MAX_AVATAR_BYTES))
+        .route_layer(from_fn_with_state(state.clone(), require_write_auth));
+
+    let listings = Router::new()
+        .route("/user/{id}/audit", get(get_user_audit))
         .route("/users", get(get_users))
-        .route("/user", post(add_user))
-        .layer(OtelInResponseLayer::default())
-        .layer(OtelAxumLayer::default())
-        .with_state(state)
+        .route("/users/page", get(get_users_page))
+        .route("/users/search", get(search_users))
+        .route("/users/recent", get(get_recent_users))
+        .route_layer(from_fn_with_state(state.clone(), advertise_max_page_size));
+
+    let api = Router::new()
+        .route("/users/count", get(count_users))
+        .route("/users/exists", get(users_exist))
+        .route("/users/stats", get(get_user_stats))
+        .route("/user/{id}", get(get_user).head(head_user))
+        .route("/user/{id}/history", get(get_user_history))
+        .route("/user/{id}/addresses", get(get_addresses))
+        .route("/user/{id}/avatar", get(get_avatar))
+        .merge(listings)
+        .route("/users/export", get(export_users))
+        .route("/users.csv", get(export_users_csv))
+        .merge(with_body_limit(
+            Router::new().route("/users/lookup", post(lookup_users)),
+            state
+                .max_body_bytes
+                .max(MAX_LOOKUP_IDS * LOOKUP_BODY_BYTES_PER_ID),
+        ))
+        .merge(writes);
+
+    // Compression wraps only the API routes; /metrics and the probes are added
+    // below and stay uncompressed, since Prometheus does not negotiate encodings.
+    let api = if compression_enabled {
+        api.layer(CompressionLayer::new())
+    } else {
+        api
+    };
+
+    let api = api
+        // Inside the metrics and tracing layers, so timed-out requests are recorded as 503s.
+        .layer(
+            ServiceBuilder::new()
+                .layer(HandleErrorLayer::new(handle_timeout_error))
+                .layer(TimeoutLayer::new(request_timeout)),
+        )
+        .layer(from_fn_with_state(state.clone(), record_request_duration))
+        .layer(OtelInResponseLayer)
+        .layer(from_fn_with_state(state.clone(), track_active_requests))
+        .layer(RequestIdLayer)
+        .layer(BaggageLayer)
+        .layer(OtelAxumLayer::default());
+
+    let api = if BASE_PATH.is_empty() {
+        api
+    } else {
+        Router::new().nest(BASE_PATH, api)
+    };
+
+    // Routes added after the layers above are not wrapped by them, which keeps
+    // synthetic probe traffic out of traces and HTTP metrics.
+    let api = api
+        .route("/health", get(health_check))
+        .route("/livez", get(livez))
+        .route("/readyz", get(readyz));
+
+    #[cfg(feature = "prometheus")]
+    let api = api.route("/metrics", get(crate::prometheus::metrics));
+
+    // Outermost, so preflight OPTIONS requests are answered before any span is created.
+    api.layer(cors.layer()).with_state(state)
 }
@@ -0,0 +1,529 @@
+#[cfg(test)]
+mod tests;
+
+use std::sync::Arc;
+use std::time::Instant;
+
+use chrono::{DateTime, Utc};
+use futures_util::stream::BoxStream;
+use opentelemetry::metrics::Histogram;
+use tracing::{Instrument, Span};
+
+use crate::error::{record_constraint_violation, unique_violation_field};
+use crate::models::{CreateUserRequest, FieldError, User, UserId};
+use crate::otel;
+use crate::repository::{
+    MergeOutcome, MergedUsers, NameMatches, UpdateOutcome, UserChanges, UserCreatedHook,
+    UserCreatedHooks, UserPage, UserQuery, UserRepository,
+};
+use crate::state::IdGenerator;
+
+/// Why a [`UserService`] call did not succeed. `AppError` turns each into a response.
+pub enum ServiceError {
+    InvalidFields(Vec<FieldError>),
+    NotFound(UserId),
+    /// The email belongs to another user. Soft-deleted users keep theirs, so they
+    /// can be restored without a clash.
+    EmailTaken,
+    VersionMismatch {
+        expected: i32,
+        actual: i32,
+    },
+    Repository(anyhow::Error),
+}
+
+impl From<anyhow::Error> for ServiceError {
+    fn from(err: anyhow::Error) -> Self {
+        Self::Repository(err)
+    }
+}
+
+pub struct CreatedUser {
+    pub user: User,
+    /// The user was created by an earlier request with the same idempotency key.
+    pub replayed: bool,
+}
+
+pub struct RestoredUser {
+    pub user: User,
+    /// `false` when the user was not deleted, so nothing changed.
+    pub restored: bool,
+}
+
+/// The rules for managing users, on top of a [`UserRepository`]. Every repository
+/// call gets its own `db.query` span and is timed on `db.client.operation.duration`.
+#[derive(Clone)]
+pub struct UserService {
+    users: Arc<dyn UserRepository + Send + Sync>,
+    ids: Arc<dyn IdGenerator + Send + Sync>,
+    /// Run for every new user before its insert commits.
+    on_created: Arc<UserCreatedHooks>,
+    db_operation_duration: Histogram<f64>,
+}
+
+impl UserService {
+    pub fn new(
+        users: Arc<dyn UserRepository + Send + Sync>,
+        ids: Arc<dyn IdGenerator + Send + Sync>,
+        on_created: Vec<Arc<dyn UserCreatedHook + Send + Sync>>,
+        db_operation_duration: Histogram<f64>,
+    ) -> Self {
+        Self {
+            users,
+            ids,
+            on_created: on_created.into(),
+            db_operation_duration,
+        }
+    }
+
+    async fn timed<T>(
+        &self,
+        operation: &'static str,
+        span: Span,
+        call: impl Future<Output = anyhow::Result<T>>,
+    ) -> anyhow::Result<T> {
+        let start = Instant::now();
+        let result = call.instrument(span).await;
+        otel::record_db_operation(&self.db_operation_duration, "users", operation, start);
+        result
+    }
+
+    /// Creates a user, or returns the one an earlier request created under the same
+    /// `idempotency_key`.
+    pub async fn create_user(
+        &self,
+        request: CreateUserRequest,
+        idempotency_key: Option<&str>,
+    ) -> Result<CreatedUser, ServiceError> {
+        request.validate().map_err(ServiceError::InvalidFields)?;
+        if let Some(key) = idempotency_key
+            && let Some(user) = self.find_by_idempotency_key(key).await?
+        {
+            return Ok(CreatedUser {
+                user,
+                replayed: true,
+            });
+        }
+
+        let email_taken = self
+            .timed(
+                "SELECT",
+                tracing::info_span!("db.query", db.statement = "SELECT user EXISTS BY email"),
+                self.users.email_exists(&[&request.email]),
+            )
+            .await?;
+        if email_taken {
+            return Err(ServiceError::EmailTaken);
+        }
+
+        // The span covers the whole transaction, hooks and commit included.
+        let inserted = self
+            .timed(
+                "INSERT",
+                tracing::info_span!(
+                    "db.query",
+                    db.statement = "INSERT user",
+                    hooks = self.on_created.len()
+                ),
+                self.users.insert(
+                    self.ids.user_id(),
+                    &request,
+                    idempotency_key,
+                    &self.on_created,
+                ),
+            )
+            .await;
+        // A concurrent request can still take the email between the check and the insert.
+        if let Err(err) = &inserted
+            && let Some(err) = err.downcast_ref()
+            && unique_violation_field(err) == Some("email")
+        {
+            record_constraint_violation(err);
+            return Err(ServiceError::EmailTaken);
+        }
+        if let Some(user) = inserted? {
+            return Ok(CreatedUser {
+                user,
+                replayed: false,
+            });
+        }
+
+        // The insert only comes back empty when a concurrent request claimed the same key.
+        let key = idempotency_key.unwrap_or_default();
+        match self.find_by_idempotency_key(key).await? {
+            Some(user) => Ok(CreatedUser {
+                user,
+                replayed: true,
+            }),
+            None => Err(anyhow::anyhow!("Idempotency key was claimed without a user").into()),
+        }
+    }
+
+    /// Creates every user in `requests` or, on any failure, none of them. The rules
+    /// are those of [`create_user`](Self::create_user), except that idempotency keys
+    /// are not supported; field errors are prefixed with the index of the request.
+    pub async fn create_users(
+        &self,
+        requests: &[CreateUserRequest],
+    ) -> Result<Vec<User>, ServiceError> {
+        let errors: Vec<FieldError> = requests
+            .iter()
+            .enumerate()
+            .filter_map(|(index, request)| request.validate().err().map(|errors| (index, errors)))
+            .flat_map(|(index, errors)| {
+                errors.into_iter().map(move |error| FieldError {
+                    field: format!("[{index}].{}", error.field),
+                    message: error.message,
+                })
+            })
+            .collect();
+        if !errors.is_empty() {
+            return Err(ServiceError::InvalidFields(errors));
+        }
+        if requests.is_empty() {
+            return Ok(Vec::new());
+        }
+
+        let emails: Vec<&str> = requests
+            .iter()
+            .map(|request| request.email.as_str())
+            .collect();
+        let email_taken = self
+            .timed(
+                "SELECT",
+                tracing::info_span!(
+                    "db.query",
+                    db.statement = "SELECT user EXISTS BY email",
+                    batch_size = requests.len()
+                ),
+                self.users.email_exists(&emails),
+            )
+            .await?;
+        if email_taken {
+            return Err(ServiceError::EmailTaken);
+        }
+
+        let new_users: Vec<_> = requests
+            .iter()
+            .map(|request| (self.ids.user_id(), request))
+            .collect();
+        let inserted = self
+            .timed(
+                "INSERT",
+                tracing::info_span!(
+                    "db.query",
+                    db.statement = "BULK INSERT users",
+                    batch_size = requests.len(),
+                    hooks = self.on_created.len()
+                ),
+                self.users.insert_many(&new_users, &self.on_created),
+            )
+            .await;
+        // Covers a concurrent insert as well as two requests in the batch sharing an email.
+        if let Err(err) = &inserted
+            && let Some(err) = err.downcast_ref()
+            && unique_violation_field(err) == Some("email")
+        {
+            record_constraint_violation(err);
+            return Err(ServiceError::EmailTaken);
+        }
+        Ok(inserted?)
+    }
+
+    async fn find_by_idempotency_key(&self, key: &str) -> anyhow::Result<Option<User>> {
+        self.timed(
+            "SELECT",
+            tracing::info_span!("db.query", db.statement = "SELECT user BY idempotency_key"),
+            self.users.find_by_idempotency_key(key),
+        )
+        .await
+    }
+
+    pub async fn get_user(&self, id: UserId, include_deleted: bool) -> Result<User, ServiceError> {
+        self.timed(
+            "SELECT",
+            tracing::info_span!(
+                "db.query",
+                db.statement = "SELECT user BY id",
+                include_deleted
+            ),
+            self.users.find_by_id(id, include_deleted),
+        )
+        .await?
+        .ok_or(ServiceError::NotFound(id))
+    }
+
+    /// The live users among `ids`, in no particular order.
+    pub async fn find_users(&self, ids: &[UserId]) -> Result<Vec<User>, ServiceError> {
+        Ok(self
+            .timed(
+                "SELECT",
+                tracing::info_span!(
+                    "db.query",
+                    db.statement = "SELECT users BY ids",
+                    requested = ids.len()
+                ),
+                self.users.find_by_ids(ids),
+            )
+            .await?)
+    }
+
+    pub async fn user_exists(
+        &self,
+        id: UserId,
+        include_deleted: bool,
+    ) -> Result<bool, ServiceError> {
+        Ok(self
+            .timed(
+                "SELECT",
+                tracing::info_span!(
+                    "db.query",
+                    db.statement = "SELECT user EXISTS",
+                    include_deleted
+                ),
+                self.users.exists(id, include_deleted),
+            )
+            .await?)
+    }
+
+    /// Live users with this name, ignoring case, and the ids of up to `limit` of them.
+    /// The names stay off the span; only the count is recorded.
+    pub async fn find_by_name(
+        &self,
+        first_name: &str,
+        last_name: &str,
+        limit: u32,
+    ) -> Result<NameMatches, ServiceError> {
+        let span = tracing::info_span!(
+            "db.query",
+            db.statement = "SELECT users BY name",
+            limit,
+            result.count = tracing::field::Empty,
+        );
+        let matches = self
+            .timed(
+                "SELECT",
+                span.clone(),
+                self.users.find_by_name(first_name, last_name, limit),
+            )
+            .await?;
+        span.record("result.count", matches.count);
+        Ok(matches)
+    }
+
+    /// One page of users. When counting was asked for, `total_count` is always set,
+    /// even for a page past the end that has no rows to carry it.
+    pub async fn list_users(&self, query: &UserQuery<'_>) -> Result<UserPage, ServiceError> {
+        let mut page = self
+            .timed(
+                "SELECT",
+                tracing::info_span!(
+                    "db.query",
+                    db.statement = "SELECT users",
+                    limit = query.limit,
+                    offset = query.offset,
+                    with_count = query.with_count,
+                    include_deleted = query.include_deleted,
+                    sort = query.sort.column(),
+                    order = query.order.keyword(),
+                ),
+                self.users.find_all(query),
+            )
+            .await?;
+        if query.with_count && page.total_count.is_none() {
+            page.total_count = Some(if query.offset == 0 {
+                0
+            } else {
+                self.count_users(query.last_name, query.include_deleted)
+                    .await?
+            });
+        }
+        Ok(page)
+    }
+
+    /// Up to `limit` users after the `after` cursor, in id order, and whether more follow.
+    pub async fn list_users_after(
+        &self,
+        after: Option<UserId>,
+        limit: u32,
+        last_name: Option<&str>,
+        include_deleted: bool,
+    ) -> Result<(Vec<User>, bool), ServiceError> {
+        // One extra row is fetched to tell whether another page follows.
+        let mut users = self
+            .timed(
+                "SELECT",
+                tracing::info_span!(
+                    "db.query",
+                    db.statement = "SELECT users AFTER id",
+                    limit,
+                    include_deleted,
+                ),
+                self.users
+                    .find_after(after, limit.saturating_add(1), last_name, include_deleted),
+            )
+            .await?;
+        let has_more = users.len() > limit as usize;
+        users.truncate(limit as usize);
+        Ok((users, has_more))
+    }
+
+    /// Every live user in id order. Unlike the other calls this one is neither spanned
+    /// nor timed, since only the caller knows when it has finished reading.
+    pub fn stream_users(&self) -> BoxStream<'_, anyhow::Result<User>> {
+        self.users.stream_live()
+    }
+
+    pub async fn search_users(
+        &self,
+        query: &str,
+        limit: u32,
+        offset: u32,
+    ) -> Result<Vec<User>, ServiceError> {
+        Ok(self
+            .timed(
+                "SELECT",
+                tracing::info_span!("db.query", db.statement = "SEARCH users", limit, offset),
+                self.users.search(query, limit, offset),
+            )
+            .await?)
+    }
+
+    pub async fn count_search_results(&self, query: &str) -> Result<i64, ServiceError> {
+        Ok(self
+            .timed(
+                "SELECT",
+                tracing::info_span!("db.query", db.statement = "COUNT users SEARCH"),
+                self.users.count_search(query),
+            )
+            .await?)
+    }
+
+    /// Live users created at or after `since`, newest first.
+    pub async fn recent_users(
+        &self,
+        since: DateTime<Utc>,
+        limit: u32,
+    ) -> Result<Vec<User>, ServiceError> {
+        Ok(self
+            .timed(
+                "SELECT",
+                tracing::info_span!(
+                    "db.query",
+                    db.statement = "SELECT users BY created_at",
+                    limit
+                ),
+                self.users.find_created_since(since, limit),
+            )
+            .await?)
+    }
+
+    pub async fn count_users(
+        &self,
+        last_name: Option<&str>,
+        include_deleted: bool,
+    ) -> Result<i64, ServiceError> {
+        Ok(self
+            .timed(
+                "SELECT",
+                tracing::info_span!("db.query", db.statement = "COUNT users", include_deleted),
+                self.users.count(last_name, include_deleted),
+            )
+            .await?)
+    }
+
+    /// Applies `changes` to a live user; `statement` names the `db.query` span.
+    pub async fn update_user(
+        &self,
+        id: UserId,
+        changes: &UserChanges<'_>,
+        statement: &'static str,
+    ) -> Result<User, ServiceError> {
+        let outcome = self
+            .timed(
+                "UPDATE",
+                tracing::info_span!("db.query", db.statement = statement),
+                self.users.update(id, changes),
+            )
+            .await?;
+        match outcome {
+            UpdateOutcome::Updated(user) => Ok(user),
+            UpdateOutcome::VersionMismatch { expected, actual } => {
+                Err(ServiceError::VersionMismatch { expected, actual })
+            }
+            UpdateOutcome::NotFound => Err(ServiceError::NotFound(id)),
+        }
+    }
+
+    /// Soft-deletes a live user.
+    pub async fn delete_user(&self, id: UserId) -> Result<(), ServiceError> {
+        let deleted = self
+            .timed(
+                "UPDATE",
+                tracing::info_span!("db.query", db.statement = "SOFT DELETE user BY id"),
+                self.users.delete(id),
+            )
+            .await?;
+        if deleted {
+            Ok(())
+        } else {
+            Err(ServiceError::NotFound(id))
+        }
+    }
+
+    /// Soft-deletes the live users among `ids` and returns how many there were.
+    pub async fn delete_users(&self, ids: &[UserId]) -> Result<u64, ServiceError> {
+        Ok(self
+            .timed(
+                "UPDATE",
+                tracing::info_span!(
+                    "db.query",
+                    db.statement = "SOFT DELETE users BY ids",
+                    requested = ids.len()
+                ),
+                self.users.delete_many(ids),
+            )
+            .await?)
+    }
+
+    /// Clears `deleted_at` on a soft-deleted user. A user that is not deleted is
+    /// returned as it is, so retries are safe.
+    pub async fn restore_user(&self, id: UserId) -> Result<RestoredUser, ServiceError> {
+        let restored = self
+            .timed(
+                "UPDATE",
+                tracing::info_span!("db.query", db.statement = "RESTORE user BY id"),
+                self.users.restore(id),
+            )
+            .await?;
+        match restored {
+            Some(user) => Ok(RestoredUser {
+                user,
+                restored: true,
+            }),
+            None => Ok(RestoredUser {
+                user: self.get_user(id, true).await?,
+                restored: false,
+            }),
+        }
+    }
+
+    /// Folds `remove` into `keep`; both must be live.
+    pub async fn merge_users(
+        &self,
+        keep: UserId,
+        remove: UserId,
+    ) -> Result<MergedUsers, ServiceError> {
+        let outcome = self
+            .timed(
+                "UPDATE",
+                tracing::info_span!("db.query", db.statement = "MERGE users"),
+                self.users.merge(keep, remove),
+            )
+            .await?;
+        match outcome {
+            MergeOutcome::Merged(merged) => Ok(merged),
+            MergeOutcome::NotFound(id) => Err(ServiceError::NotFound(id)),
+        }
+    }
+}
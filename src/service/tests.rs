@@ -0,0 +1,439 @@
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::{Arc, Mutex};
+
+use async_trait::async_trait;
+use opentelemetry::metrics::MeterProvider;
+use opentelemetry_sdk::metrics::SdkMeterProvider;
+use uuid::Uuid;
+
+use super::{ServiceError, UserService};
+use crate::models::{CreateUserRequest, FirstName, SortField, SortOrder, User, UserId};
+use crate::otel;
+use crate::repository::{InMemoryUserRepository, UserChanges, UserCreatedHook, UserQuery};
+use crate::state::{IdGenerator, TimeOrderedIds};
+
+fn service(users: impl IntoIterator<Item = User>) -> UserService {
+    service_with_hooks(users, Vec::new())
+}
+
+fn service_with_hooks(
+    users: impl IntoIterator<Item = User>,
+    on_created: Vec<Arc<dyn UserCreatedHook + Send + Sync>>,
+) -> UserService {
+    service_with(users, Arc::new(TimeOrderedIds), on_created)
+}
+
+fn service_with(
+    users: impl IntoIterator<Item = User>,
+    ids: Arc<dyn IdGenerator + Send + Sync>,
+    on_created: Vec<Arc<dyn UserCreatedHook + Send + Sync>>,
+) -> UserService {
+    let meter = SdkMeterProvider::builder().build().meter("test");
+    UserService::new(
+        Arc::new(InMemoryUserRepository::with_users(users)),
+        ids,
+        on_created,
+        otel::db_operation_duration_histogram(&meter),
+    )
+}
+
+/// Hands out 1, 2, 3, ... as ids.
+#[derive(Default)]
+struct SequentialIds(AtomicU64);
+
+impl IdGenerator for SequentialIds {
+    fn user_id(&self) -> UserId {
+        let next = self.0.fetch_add(1, Ordering::Relaxed) + 1;
+        Uuid::from_u128(u128::from(next)).into()
+    }
+}
+
+/// Remembers the users it was called with, and fails every call when `fail` is set.
+#[derive(Default)]
+struct RecordingHook {
+    seen: Mutex<Vec<UserId>>,
+    fail: bool,
+}
+
+#[async_trait]
+impl UserCreatedHook for RecordingHook {
+    async fn user_created(&self, user: &User) -> anyhow::Result<()> {
+        self.seen.lock().unwrap().push(user.id);
+        anyhow::ensure!(!self.fail, "welcome event was not published");
+        Ok(())
+    }
+}
+
+fn user(first_name: &str, last_name: &str) -> User {
+    User {
+        id: UserId::random(),
+        first_name: first_name.to_owned().try_into().unwrap(),
+        middle_name: None,
+        last_name: last_name.to_owned().try_into().unwrap(),
+        email: format!("{}@example.com", first_name.to_lowercase()),
+        created_at: "2026-01-01T00:00:00Z".parse().unwrap(),
+        updated_at: "2026-01-01T00:00:00Z".parse().unwrap(),
+        deleted_at: None,
+        version: 1,
+    }
+}
+
+fn request(first_name: &str, email: &str) -> CreateUserRequest {
+    CreateUserRequest {
+        first_name: first_name.to_owned().try_into().unwrap(),
+        middle_name: None,
+        last_name: "Hopper".to_owned().try_into().unwrap(),
+        email: email.to_owned(),
+    }
+}
+
+fn query(limit: u32, offset: u32) -> UserQuery<'static> {
+    UserQuery {
+        limit,
+        offset,
+        last_name: None,
+        include_deleted: false,
+        sort: SortField::FirstName,
+        order: SortOrder::Asc,
+        with_count: true,
+    }
+}
+
+#[tokio::test]
+async fn create_user_stores_a_user_that_can_be_fetched() {
+    let service = service([]);
+
+    let created = service
+        .create_user(request("Grace", "grace@example.com"), None)
+        .await
+        .ok()
+        .expect("created");
+    assert!(!created.replayed);
+    assert_eq!(created.user.version, 1);
+
+    let fetched = service
+        .get_user(created.user.id, false)
+        .await
+        .ok()
+        .expect("found");
+    assert_eq!(fetched.first_name, "Grace");
+    assert_eq!(fetched.email, "grace@example.com");
+}
+
+#[tokio::test]
+async fn create_user_takes_the_id_from_the_generator() {
+    let service = service_with([], Arc::new(SequentialIds::default()), Vec::new());
+
+    let created = service
+        .create_user(request("Grace", "grace@example.com"), None)
+        .await
+        .ok()
+        .expect("created");
+
+    assert_eq!(created.user.id, Uuid::from_u128(1).into());
+}
+
+#[test]
+fn time_ordered_ids_increase() {
+    let first = TimeOrderedIds.user_id();
+    let second = TimeOrderedIds.user_id();
+
+    assert!(first < second, "{first} should sort before {second}");
+    assert_eq!(second.as_uuid().get_version_num(), 7);
+}
+
+#[tokio::test]
+async fn create_user_runs_the_created_hooks() {
+    let hook = Arc::new(RecordingHook::default());
+    let service = service_with_hooks([], vec![hook.clone()]);
+
+    let created = service
+        .create_user(request("Grace", "grace@example.com"), None)
+        .await
+        .ok()
+        .expect("created");
+
+    assert_eq!(*hook.seen.lock().unwrap(), [created.user.id]);
+}
+
+#[tokio::test]
+async fn create_user_keeps_nothing_when_a_created_hook_fails() {
+    let hook = Arc::new(RecordingHook {
+        fail: true,
+        ..Default::default()
+    });
+    let service = service_with_hooks([], vec![hook.clone()]);
+
+    let result = service
+        .create_user(request("Grace", "grace@example.com"), None)
+        .await;
+
+    assert!(matches!(result, Err(ServiceError::Repository(_))));
+    assert_eq!(hook.seen.lock().unwrap().len(), 1);
+    assert_eq!(service.count_users(None, true).await.ok(), Some(0));
+}
+
+#[tokio::test]
+async fn create_user_rejects_invalid_fields() {
+    let service = service([]);
+
+    let result = service
+        .create_user(request("Grace", "grace.example.com"), None)
+        .await;
+
+    let Err(ServiceError::InvalidFields(errors)) = result else {
+        panic!("expected invalid fields");
+    };
+    assert_eq!(errors[0].field, "email");
+    assert_eq!(service.count_users(None, true).await.ok(), Some(0));
+}
+
+#[tokio::test]
+async fn create_user_rejects_an_email_taken_by_a_deleted_user() {
+    let mut deleted = user("Ada", "Lovelace");
+    deleted.deleted_at = Some("2026-01-02T00:00:00Z".parse().unwrap());
+    let service = service([deleted]);
+
+    let result = service
+        .create_user(request("Augusta", "ADA@example.com"), None)
+        .await;
+
+    assert!(matches!(result, Err(ServiceError::EmailTaken)));
+}
+
+#[tokio::test]
+async fn create_user_replays_the_user_as_it_was_created() {
+    let service = service([]);
+    let created = service
+        .create_user(request("Grace", "grace@example.com"), Some("retry-1"))
+        .await
+        .ok()
+        .expect("created");
+    let brewster = FirstName::try_from("Brewster".to_owned()).unwrap();
+    let rename = UserChanges {
+        first_name: Some(&brewster),
+        middle_name: None,
+        last_name: None,
+        expected_version: None,
+    };
+    service
+        .update_user(created.user.id, &rename, "PATCH user")
+        .await
+        .ok()
+        .expect("updated");
+
+    let replayed = service
+        .create_user(request("Alan", "alan@example.com"), Some("retry-1"))
+        .await
+        .ok()
+        .expect("replayed");
+
+    assert!(replayed.replayed);
+    assert_eq!(replayed.user.id, created.user.id);
+    assert_eq!(replayed.user.first_name, "Grace");
+    assert_eq!(replayed.user.version, 1);
+    assert_eq!(service.count_users(None, true).await.ok(), Some(1));
+}
+
+#[tokio::test]
+async fn create_users_runs_the_created_hooks_for_every_user() {
+    let hook = Arc::new(RecordingHook::default());
+    let service = service_with([], Arc::new(SequentialIds::default()), vec![hook.clone()]);
+
+    let created = service
+        .create_users(&[
+            request("Grace", "grace@example.com"),
+            request("Alan", "alan@example.com"),
+        ])
+        .await
+        .ok()
+        .expect("created");
+
+    let ids: Vec<UserId> = created.iter().map(|user| user.id).collect();
+    assert_eq!(ids, [Uuid::from_u128(1).into(), Uuid::from_u128(2).into()]);
+    assert_eq!(*hook.seen.lock().unwrap(), ids);
+}
+
+#[tokio::test]
+async fn create_users_keeps_nothing_when_a_created_hook_fails() {
+    let hook = Arc::new(RecordingHook {
+        fail: true,
+        ..Default::default()
+    });
+    let service = service_with_hooks([], vec![hook]);
+
+    let result = service
+        .create_users(&[request("Grace", "grace@example.com")])
+        .await;
+
+    assert!(matches!(result, Err(ServiceError::Repository(_))));
+    assert_eq!(service.count_users(None, true).await.ok(), Some(0));
+}
+
+#[tokio::test]
+async fn create_users_rejects_an_email_taken_by_another_user() {
+    let service = service([user("Ada", "Lovelace")]);
+
+    let result = service
+        .create_users(&[
+            request("Grace", "grace@example.com"),
+            request("Augusta", "ADA@example.com"),
+        ])
+        .await;
+
+    assert!(matches!(result, Err(ServiceError::EmailTaken)));
+    assert_eq!(service.count_users(None, true).await.ok(), Some(1));
+}
+
+#[tokio::test]
+async fn create_users_names_the_request_with_an_invalid_field() {
+    let service = service([]);
+
+    let result = service
+        .create_users(&[
+            request("Grace", "grace@example.com"),
+            request("Alan", "alan.example.com"),
+        ])
+        .await;
+
+    let Err(ServiceError::InvalidFields(errors)) = result else {
+        panic!("expected invalid fields");
+    };
+    assert_eq!(errors[0].field, "[1].email");
+    assert_eq!(service.count_users(None, true).await.ok(), Some(0));
+}
+
+#[tokio::test]
+async fn get_user_hides_deleted_users_unless_asked() {
+    let mut deleted = user("Ada", "Lovelace");
+    deleted.deleted_at = Some("2026-01-02T00:00:00Z".parse().unwrap());
+    let id = deleted.id;
+    let service = service([deleted]);
+
+    assert!(
+        matches!(service.get_user(id, false).await, Err(ServiceError::NotFound(missing)) if missing == id)
+    );
+    assert!(service.get_user(id, true).await.is_ok());
+    assert_eq!(service.user_exists(id, false).await.ok(), Some(false));
+    assert_eq!(service.user_exists(id, true).await.ok(), Some(true));
+}
+
+#[tokio::test]
+async fn list_users_counts_every_match() {
+    let service = service([
+        user("Ada", "Lovelace"),
+        user("Grace", "Hopper"),
+        user("Alan", "Turing"),
+    ]);
+
+    let page = service.list_users(&query(2, 0)).await.ok().expect("page");
+
+    let names: Vec<&str> = page.users.iter().map(|user| &*user.first_name).collect();
+    assert_eq!(names, ["Ada", "Alan"]);
+    assert_eq!(page.total_count, Some(3));
+}
+
+#[tokio::test]
+async fn list_users_counts_a_page_past_the_end() {
+    let service = service([user("Ada", "Lovelace"), user("Grace", "Hopper")]);
+
+    let page = service.list_users(&query(10, 5)).await.ok().expect("page");
+
+    assert!(page.users.is_empty());
+    assert_eq!(page.total_count, Some(2));
+}
+
+#[tokio::test]
+async fn list_users_after_reports_whether_more_follow() {
+    let service = service([
+        user("Ada", "Lovelace"),
+        user("Grace", "Hopper"),
+        user("Alan", "Turing"),
+    ]);
+
+    let (users, has_more) = service
+        .list_users_after(None, 2, None, false)
+        .await
+        .ok()
+        .expect("page");
+    assert_eq!(users.len(), 2);
+    assert!(has_more);
+
+    let (users, has_more) = service
+        .list_users_after(None, u32::MAX, None, false)
+        .await
+        .ok()
+        .expect("page");
+    assert_eq!(users.len(), 3);
+    assert!(!has_more);
+}
+
+#[tokio::test]
+async fn update_user_checks_the_expected_version() {
+    let ada = user("Ada", "Lovelace");
+    let id = ada.id;
+    let service = service([ada]);
+    let augusta = FirstName::try_from("Augusta".to_owned()).unwrap();
+    let stale = UserChanges {
+        first_name: Some(&augusta),
+        middle_name: None,
+        last_name: None,
+        expected_version: Some(7),
+    };
+
+    let result = service.update_user(id, &stale, "PATCH user").await;
+    assert!(matches!(
+        result,
+        Err(ServiceError::VersionMismatch {
+            expected: 7,
+            actual: 1
+        })
+    ));
+
+    let current = UserChanges {
+        expected_version: Some(1),
+        ..stale
+    };
+    let updated = service
+        .update_user(id, &current, "PATCH user")
+        .await
+        .ok()
+        .expect("updated");
+    assert_eq!(updated.first_name, "Augusta");
+    assert_eq!(updated.last_name, "Lovelace");
+    assert_eq!(updated.version, 2);
+}
+
+#[tokio::test]
+async fn update_user_reports_a_missing_user() {
+    let service = service([]);
+    let id = UserId::random();
+    let nobody = FirstName::try_from("Nobody".to_owned()).unwrap();
+    let changes = UserChanges {
+        first_name: Some(&nobody),
+        middle_name: None,
+        last_name: None,
+        expected_version: None,
+    };
+
+    let result = service.update_user(id, &changes, "PUT user").await;
+
+    assert!(matches!(result, Err(ServiceError::NotFound(missing)) if missing == id));
+}
+
+#[tokio::test]
+async fn delete_user_only_deletes_once() {
+    let ada = user("Ada", "Lovelace");
+    let id = ada.id;
+    let service = service([ada]);
+
+    assert!(service.delete_user(id).await.is_ok());
+    assert!(matches!(
+        service.delete_user(id).await,
+        Err(ServiceError::NotFound(_))
+    ));
+    assert!(matches!(
+        service.get_user(id, false).await,
+        Err(ServiceError::NotFound(_))
+    ));
+}
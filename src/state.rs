@@ -1,8 +1,60 @@
-use opentelemetry::metrics::Counter;
-use sqlx::PgPool;
+use std::sync::{Arc, atomic::AtomicBool};
+
+use opentelemetry::metrics::{Counter, Histogram, UpDownCounter};
+use uuid::Uuid;
+
+use crate::middleware::WriteAuth;
+use crate::models::UserId;
+use crate::pagination::PageSize;
+use crate::repository::{
+    AddressRepository, AuditRepository, AvatarRepository, DatabaseHealth, StatsRepository,
+};
+use crate::service::UserService;
+
+/// Where ids for new users come from, so tests can hand out known ones.
+pub trait IdGenerator {
+    fn user_id(&self) -> UserId;
+}
+
+/// UUIDv7 ids, which start with a timestamp and increase within the process. New rows
+/// land at the end of the primary-key index instead of anywhere in it.
+pub struct TimeOrderedIds;
+
+impl IdGenerator for TimeOrderedIds {
+    fn user_id(&self) -> UserId {
+        Uuid::now_v7().into()
+    }
+}
 
 #[derive(Clone)]
 pub struct AppState {
-    pub db: PgPool,
+    pub users: UserService,
+    pub addresses: Arc<dyn AddressRepository + Send + Sync>,
+    pub audit: Arc<dyn AuditRepository + Send + Sync>,
+    pub avatars: Arc<dyn AvatarRepository + Send + Sync>,
+    pub stats: Arc<dyn StatsRepository + Send + Sync>,
+    pub health: Arc<dyn DatabaseHealth + Send + Sync>,
+    pub ready_flag: Arc<AtomicBool>,
+    pub max_bulk_users: usize,
+    pub max_body_bytes: usize,
+    pub page_size: PageSize,
+    /// Path prefix the API is served under, used to build `Location` headers.
+    pub base_path: Arc<str>,
+    pub admin_api_key: Option<Arc<str>>,
+    pub write_auth: WriteAuth,
+    /// Default for `?envelope=`, from `RESPONSE_ENVELOPE`.
+    pub response_envelope: bool,
     pub users_created_counter: Counter<u64>,
+    pub users_updated_counter: Counter<u64>,
+    pub users_deleted_counter: Counter<u64>,
+    pub users_restored_counter: Counter<u64>,
+    pub users_conflict_counter: Counter<u64>,
+    pub users_merged_counter: Counter<u64>,
+    pub http_request_duration: Histogram<f64>,
+    pub http_active_requests: UpDownCounter<i64>,
+    pub db_operation_duration: Histogram<f64>,
+    pub health_db_check_duration: Histogram<f64>,
+    pub avatar_size: Histogram<u64>,
+    #[cfg(feature = "prometheus")]
+    pub prometheus: prometheus::Registry,
 }